@@ -0,0 +1,59 @@
+// Bloom filter over an SSTable's keys, sized for roughly a 1% false-positive rate.
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+pub struct Bloom {
+    bits: Vec<u8>,
+    hashes: u32,
+}
+
+// splitmix64 finalizer. The filter is persisted, so the hash must be stable across runs and
+// builds, which rules out std's DefaultHasher.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+impl Bloom {
+    pub fn new(num_keys: usize) -> Bloom {
+        let n = num_keys.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-n * FALSE_POSITIVE_RATE.ln() / (ln2 * ln2)).ceil().max(8.0);
+        let hashes = ((num_bits / n) * ln2).round().max(1.0) as u32;
+        Bloom {
+            bits: vec![0u8; (num_bits as usize).div_ceil(8)],
+            hashes,
+        }
+    }
+
+    // Double hashing: bit i is h1 + i*h2, which behaves like `hashes` independent hashes.
+    fn positions(&self, key: u64) -> impl Iterator<Item = usize> {
+        let num_bits = self.bits.len() as u64 * 8;
+        let h1 = mix(key);
+        let h2 = mix(h1) | 1;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+
+    pub fn insert(&mut self, key: u64) {
+        for pos in self.positions(key).collect::<Vec<_>>() {
+            self.bits[pos / 8] |= 1 << (pos % 8);
+        }
+    }
+
+    pub fn contains(&self, key: u64) -> bool {
+        self.positions(key).all(|pos| self.bits[pos / 8] & (1 << (pos % 8)) != 0)
+    }
+
+    pub fn hashes(&self) -> u32 {
+        self.hashes
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+
+    pub fn from_bytes(bits: Vec<u8>, hashes: u32) -> Bloom {
+        Bloom { bits, hashes }
+    }
+}
@@ -0,0 +1,153 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::{CompactionStyle, Compression, DingoError, DingoStore, Durability, Key, MergeOperator, Value, BLOCK_SIZE, COMPACT_LIM, SIZE_THRESH};
+
+// Configures a DingoStore before it's created. Anything left unset keeps the defaults that
+// DingoStore::new uses.
+pub struct DingoStoreBuilder<V: Value = String> {
+    pub(super) fname: String,
+    pub(super) data_dir: PathBuf,
+    pub(super) memtable_size_bytes: u32,
+    // None follows memtable_size_bytes.
+    pub(super) max_value_bytes: Option<u32>,
+    pub(super) compaction_trigger: usize,
+    pub(super) compaction_style: CompactionStyle,
+    pub(super) compaction_interval: Option<Duration>,
+    pub(super) compression: Compression,
+    pub(super) cache_capacity: usize,
+    pub(super) block_size: u32,
+    pub(super) mmap: bool,
+    pub(super) preload_indexes: bool,
+    pub(super) durability: Durability,
+    pub(super) merge_operator: Option<MergeOperator<V>>,
+}
+
+impl<V: Value> DingoStoreBuilder<V> {
+    pub fn new(fname: &str) -> DingoStoreBuilder<V> {
+        DingoStoreBuilder {
+            fname: fname.to_string(),
+            data_dir: PathBuf::new(),
+            memtable_size_bytes: SIZE_THRESH,
+            max_value_bytes: None,
+            compaction_trigger: COMPACT_LIM,
+            compaction_style: CompactionStyle::SizeTiered,
+            compaction_interval: None,
+            compression: Compression::None,
+            cache_capacity: 0,
+            block_size: BLOCK_SIZE,
+            mmap: false,
+            preload_indexes: false,
+            durability: Durability::SyncOnFlush,
+            merge_operator: None,
+        }
+    }
+
+    // Directory the store's files are created in, created if it doesn't exist. Defaults to the
+    // current directory. fname is then only the files' base name: the WAL is
+    // {data_dir}/{fname}.wal, next to {fname}.manifest and the {fname}_{ts}.data SSTables, and
+    // build() and open() look for them there.
+    pub fn data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = data_dir.into();
+        self
+    }
+
+    // How large the memtable may grow before it's flushed to an SSTable.
+    pub fn memtable_size_bytes(mut self, memtable_size_bytes: u32) -> Self {
+        self.memtable_size_bytes = memtable_size_bytes;
+        self
+    }
+
+    // Writes whose value encodes to more than this many bytes are refused with
+    // DingoError::ValueTooLarge. Defaults to memtable_size_bytes, so a single value never
+    // overflows the memtable on its own.
+    pub fn max_value_bytes(mut self, max_value_bytes: u32) -> Self {
+        self.max_value_bytes = Some(max_value_bytes);
+        self
+    }
+
+    // Compaction runs once there are more than this many SSTables, or with leveled compaction,
+    // more than this many in level 0.
+    pub fn compaction_trigger(mut self, compaction_trigger: usize) -> Self {
+        self.compaction_trigger = compaction_trigger;
+        self
+    }
+
+    // SizeTiered by default. Stores can switch from any style to any other between runs; the
+    // tables already on disk are taken over as they are.
+    pub fn compaction_style(mut self, compaction_style: CompactionStyle) -> Self {
+        self.compaction_style = compaction_style;
+        self
+    }
+
+    // Runs DingoStore::compact_now every `interval` on a background thread for as long as the
+    // store is open, so a store that stops taking writes still gets its SSTables merged and its
+    // tombstones dropped. Off by default.
+    pub fn compaction_interval(mut self, interval: Duration) -> Self {
+        self.compaction_interval = Some(interval);
+        self
+    }
+
+    // Codec for the values in SSTables written from now on. Existing files are still read
+    // whatever they were written with. Off by default.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    // How many SSTable lookups to remember, so hot keys don't go back to disk. Off (0) by default.
+    pub fn cache_capacity(mut self, entries: usize) -> Self {
+        self.cache_capacity = entries;
+        self
+    }
+
+    // SSTables written from now on group their records into blocks of this many bytes, each
+    // starting on a multiple of the block size, so a lookup reads a single aligned block. 0 writes
+    // records back to back instead. 4096 by default.
+    pub fn block_size(mut self, bytes: u32) -> Self {
+        self.block_size = bytes;
+        self
+    }
+
+    // Memory-maps each SSTable the first time a lookup reads it and parses records straight out
+    // of the mapping from then on, rather than opening and reading the file on every lookup. Off
+    // by default. Scans and compaction still read through the file.
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    // Loads every SSTable's Bloom filter and offsets as the store is opened, so the first lookup
+    // in each file doesn't have to read its footer first. What that holds in memory grows with the
+    // tables' indexes rather than their data. Off by default, which loads each file's on its
+    // first lookup.
+    pub fn preload_indexes(mut self, preload: bool) -> Self {
+        self.preload_indexes = preload;
+        self
+    }
+
+    // When writes are fsynced. SyncOnFlush by default.
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    // How DingoStore::merge folds operands into values, e.g. add_integers. None by default, which
+    // leaves merge() unavailable. A store holding merge operands has to be reopened with the
+    // same operator.
+    pub fn merge_operator(mut self, operator: MergeOperator<V>) -> Self {
+        self.merge_operator = Some(operator);
+        self
+    }
+
+    // Creates the store, or opens the one a previous run left behind: its WAL is replayed and
+    // its SSTables are loaded.
+    pub fn build<K: Key>(self) -> Result<DingoStore<K, V>, DingoError> {
+        DingoStore::from_builder(self)
+    }
+
+    // Same as build().
+    pub fn open<K: Key>(self) -> Result<DingoStore<K, V>, DingoError> {
+        self.build()
+    }
+}
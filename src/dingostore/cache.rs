@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+
+// Least-recently-used map holding up to `capacity` entries; a capacity of 0 disables it. Every
+// access stamps the entry with a fresh tick, and the entry with the oldest tick is evicted first.
+pub struct Lru<K, V> {
+    capacity: usize,
+    tick: u64,
+    entries: BTreeMap<K, (V, u64)>,
+    // Tick -> key, oldest first.
+    order: BTreeMap<u64, K>,
+    // Bumped whenever entries are invalidated, see insert_if_current.
+    generation: u64,
+}
+
+impl<K: Ord + Clone, V: Clone> Lru<K, V> {
+    pub fn new(capacity: usize) -> Lru<K, V> {
+        Lru { capacity, tick: 0, entries: BTreeMap::new(), order: BTreeMap::new(), generation: 0 }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        self.tick += 1;
+        let (val, tick) = self.entries.get_mut(key)?;
+        self.order.remove(tick);
+        *tick = self.tick;
+        self.order.insert(self.tick, key.clone());
+        Some(val.clone())
+    }
+
+    pub fn insert(&mut self, key: K, val: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (val, self.tick));
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Inserts the entry unless something was invalidated since `generation` was read: a value
+    // looked up in the meantime may already be out of date.
+    pub fn insert_if_current(&mut self, generation: u64, key: K, val: V) {
+        if generation == self.generation {
+            self.insert(key, val);
+        }
+    }
+
+    // Drops the key's entry, and turns away entries for lookups already under way.
+    pub fn invalidate(&mut self, key: &K) {
+        self.remove(key);
+        self.generation += 1;
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some((_, tick)) = self.entries.remove(key) {
+            self.order.remove(&tick);
+        }
+    }
+
+    // Like invalidate, for every key.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.generation += 1;
+    }
+}
@@ -0,0 +1,42 @@
+use super::DingoError;
+
+// Codec ids, stored as the first byte of every value payload in a compressed SSTable.
+const RAW: u8 = 0;
+const LZ4: u8 = 1;
+
+// How SSTable values are compressed. Each value is compressed on its own, so a lookup only
+// decompresses the record it's after.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lz4,
+}
+
+impl Compression {
+    // Prefixes the payload with its codec id. Values that don't shrink are stored raw.
+    pub(super) fn compress(self, payload: &[u8]) -> Vec<u8> {
+        if self == Compression::Lz4 {
+            let compressed = lz4_flex::compress_prepend_size(payload);
+            if compressed.len() < payload.len() {
+                let mut bytes = Vec::with_capacity(1 + compressed.len());
+                bytes.push(LZ4);
+                bytes.extend_from_slice(&compressed);
+                return bytes;
+            }
+        }
+        let mut bytes = Vec::with_capacity(1 + payload.len());
+        bytes.push(RAW);
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+}
+
+// Undoes compress. `corrupt` builds the error for an unknown codec or a payload that won't
+// decompress, so it can point at the record.
+pub(super) fn decompress(bytes: &[u8], corrupt: impl Fn() -> DingoError) -> Result<Vec<u8>, DingoError> {
+    match bytes.split_first() {
+        Some((&RAW, payload)) => Ok(payload.to_vec()),
+        Some((&LZ4, payload)) => lz4_flex::decompress_size_prepended(payload).map_err(|_| corrupt()),
+        _ => Err(corrupt()),
+    }
+}
@@ -0,0 +1,45 @@
+use std::fs::File;
+use std::path::Path;
+
+// When the store fsyncs what it writes. Anything not yet synced survives the process dying, but
+// can be lost if the machine goes down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Durability {
+    // Never fsyncs, for stores that can be thrown away. A crash can lose the tail of the WAL and
+    // leave recent SSTables or the manifest half-written.
+    NoSync,
+    // Fsyncs every SSTable and manifest as it's written, but not the WAL, so a crash can lose the
+    // writes since the last flush. The default.
+    SyncOnFlush,
+    // Also fsyncs the WAL before every write returns.
+    SyncEveryWrite,
+}
+
+impl Durability {
+    // Whether SSTables, the manifest and rewritten logs are synced before they're relied on.
+    pub(super) fn sync_files(self) -> bool {
+        self != Durability::NoSync
+    }
+
+    // Fsyncs the directory `path` is in after the file was created, renamed or removed, so the
+    // change to the directory survives a crash along with the file itself.
+    pub(super) fn sync_dir_of(self, path: &str) -> std::io::Result<()> {
+        match self.sync_files() {
+            true => sync_dir_of(path),
+            false => Ok(()),
+        }
+    }
+
+    pub(super) fn sync_writes(self) -> bool {
+        self == Durability::SyncEveryWrite
+    }
+}
+
+// Fsyncs the directory `path` is in, whatever the durability setting.
+pub(super) fn sync_dir_of(path: &str) -> std::io::Result<()> {
+    let dir = match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
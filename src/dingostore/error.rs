@@ -0,0 +1,84 @@
+use std::fmt;
+use std::sync::PoisonError;
+
+#[derive(Debug)]
+pub enum DingoError {
+    Io(std::io::Error),
+    // A value couldn't be encoded to or decoded from its bincode bytes.
+    Codec(bincode::Error),
+    // An SSTable record failed its checksum or ran past the end of the file.
+    Corruption { file: String, offset: u64 },
+    // Another thread panicked while holding one of the store's locks.
+    LockPoisoned,
+    // A column family name that's empty or has characters other than ASCII letters, digits, '-'
+    // and '_'.
+    InvalidFamilyName(String),
+    // A value (or merge operand) whose encoding is longer than max_value_bytes allows. `key` is
+    // the key's encoding, as Key::encode gives it.
+    ValueTooLarge { key: Vec<u8>, len: u64 },
+    // A merge operand has to be applied but the store was built without a merge operator.
+    NoMergeOperator,
+    // A string value read back from `file` isn't valid UTF-8. `key` is the key's encoding, as
+    // Key::encode gives it.
+    InvalidUtf8 { key: Vec<u8>, file: String },
+    // An SSTable or manifest written in a format version this build can't read.
+    UnsupportedVersion { file: String, version: u8 },
+}
+
+impl fmt::Display for DingoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DingoError::Io(e) => write!(f, "I/O error: {}", e),
+            DingoError::Codec(e) => write!(f, "value encoding error: {}", e),
+            DingoError::Corruption { file, offset } => {
+                write!(f, "corrupt record in {} at offset {}", file, offset)
+            }
+            DingoError::LockPoisoned => write!(f, "store lock poisoned"),
+            DingoError::InvalidFamilyName(name) => write!(f, "invalid column family name {:?}", name),
+            DingoError::ValueTooLarge { key, len } => {
+                write!(f, "value of {} bytes for key {:02x?} is too large", len, key)
+            }
+            DingoError::NoMergeOperator => write!(f, "no merge operator configured"),
+            DingoError::InvalidUtf8 { key, file } => {
+                write!(f, "value for key {:02x?} in {} is not valid UTF-8", key, file)
+            }
+            DingoError::UnsupportedVersion { file, version } => {
+                write!(f, "{} has unsupported format version {}", file, version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DingoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DingoError::Io(e) => Some(e),
+            DingoError::Codec(e) => Some(e),
+            DingoError::Corruption { .. }
+            | DingoError::LockPoisoned
+            | DingoError::InvalidFamilyName(_)
+            | DingoError::ValueTooLarge { .. }
+            | DingoError::NoMergeOperator
+            | DingoError::InvalidUtf8 { .. }
+            | DingoError::UnsupportedVersion { .. } => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for DingoError {
+    fn from(e: std::io::Error) -> Self {
+        DingoError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for DingoError {
+    fn from(e: bincode::Error) -> Self {
+        DingoError::Codec(e)
+    }
+}
+
+impl<T> From<PoisonError<T>> for DingoError {
+    fn from(_: PoisonError<T>) -> Self {
+        DingoError::LockPoisoned
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::tombstone::RangeTombstone;
+use super::{encode_range_delete, entry_size, manifest, DingoError, Durability, Entry, Frozen, Key, Levels, Lru, Memtable, MergeOperator, Value};
+
+// A column family: a key space with its own memtables and SSTables. The store's own keys are the
+// default family, named "".
+pub(super) struct Family<K: Key, V: Value> {
+    pub(super) name: String,
+    // Shared with any snapshot taken since the last write; the next write copies it first. The
+    // memtables and the SSTable list are behind RwLocks so any number of reads can run
+    // alongside each other, only waiting while a write or flush swaps something out.
+    pub(super) objs: RwLock<Arc<Memtable<K, V>>>,
+    // A full memtable that a background thread is writing out to an SSTable. Reads still
+    // consult it until the SSTable is registered in flushed_files.
+    pub(super) immutable: RwLock<Frozen<K, V>>,
+    pub(super) treesize: AtomicU32,
+    pub(super) flushed_files: RwLock<Levels<K>>,
+    // What recent reads found in the SSTables, None for keys they don't hold. Entries are
+    // dropped as soon as their key is written to, and all at once when a range is deleted.
+    pub(super) cache: Mutex<Lru<K, Entry<V>>>,
+    pub(super) merge_operator: Option<MergeOperator<V>>,
+}
+
+impl<K: Key, V: Value> Family<K, V> {
+    fn new(name: &str, cache_capacity: usize, merge_operator: Option<MergeOperator<V>>) -> Family<K, V> {
+        Family {
+            name: name.to_string(),
+            objs: RwLock::new(Arc::new(Memtable::default())),
+            immutable: RwLock::new(None),
+            treesize: AtomicU32::new(0),
+            flushed_files: RwLock::new(Levels::new()),
+            cache: Mutex::new(Lru::new(cache_capacity)),
+            merge_operator,
+        }
+    }
+
+    // A merge is laid over what the memtable has for the key, if anything, so a merge into a
+    // deleted range starts from no value rather than waiting on older data.
+    pub(super) fn apply(&self, key: K, entry: Entry<V>) -> Result<(), DingoError> {
+        let mut objs = self.objs.write()?;
+        let objs = Arc::make_mut(&mut objs);
+        let entry = match objs.lookup(&key) {
+            Some(old) if entry.is_merge() => entry.over(old, self.merge_operator)?,
+            _ => entry,
+        };
+        let new_size = entry_size(&entry)?;
+        if let Some(old) = objs.entries.get(&key) {
+            self.treesize.fetch_sub(entry_size(old)?, Ordering::SeqCst);
+        } else {
+            self.treesize.fetch_add(key.encode().len() as u32, Ordering::SeqCst);
+        }
+        self.treesize.fetch_add(new_size, Ordering::SeqCst);
+        self.cache.lock()?.invalidate(&key);
+        objs.entries.insert(key, entry);
+        Ok(())
+    }
+
+    // What treesize comes to once the entry is applied, counted the way apply counts it: an entry
+    // replacing one already in the memtable only adds the difference in size. A merge folded
+    // into an entry already there is counted as adding its own size on top.
+    pub(super) fn size_with(&self, key: &K, entry: &Entry<V>) -> Result<u64, DingoError> {
+        let size = self.treesize.load(Ordering::SeqCst) as u64 + entry_size(entry)? as u64;
+        Ok(match self.objs.read()?.entries.get(key) {
+            Some(old) if !entry.is_merge() => size - entry_size(old)? as u64,
+            Some(_) => size,
+            None => size + key.encode().len() as u64,
+        })
+    }
+
+    // Drops the memtable's entries in the tombstone's range, which it shadows from then on along
+    // with everything older.
+    pub(super) fn delete_range(&self, tombstone: RangeTombstone<K>) -> Result<(), DingoError> {
+        let mut objs = self.objs.write()?;
+        let objs = Arc::make_mut(&mut objs);
+        let covered: Vec<K> = objs.entries.range(tombstone.bounds()).map(|(key, _)| key.clone()).collect();
+        for key in covered {
+            let old = objs.entries.remove(&key).unwrap();
+            self.treesize.fetch_sub(key.encode().len() as u32 + entry_size(&old)?, Ordering::SeqCst);
+        }
+        self.treesize.fetch_add(encode_range_delete(&tombstone).len() as u32, Ordering::SeqCst);
+        self.cache.lock()?.clear();
+        objs.range_tombstones.push(tombstone);
+        Ok(())
+    }
+}
+
+// Every column family of a store, and the manifest listing all of their SSTables.
+pub(super) struct Families<K: Key, V: Value> {
+    by_name: RwLock<BTreeMap<String, Arc<Family<K, V>>>>,
+    cache_capacity: usize,
+    merge_operator: Option<MergeOperator<V>>,
+    manifest_path: String,
+    durability: Durability,
+    // Held from reading the SSTable lists until the new manifest is in place, so the last
+    // manifest written always lists the latest tables.
+    manifest: Mutex<()>,
+}
+
+impl<K: Key, V: Value> Families<K, V> {
+    pub(super) fn new(
+        manifest_path: String,
+        durability: Durability,
+        cache_capacity: usize,
+        merge_operator: Option<MergeOperator<V>>,
+    ) -> Families<K, V> {
+        let mut by_name = BTreeMap::new();
+        by_name.insert(String::new(), Arc::new(Family::new("", cache_capacity, merge_operator)));
+        Families {
+            by_name: RwLock::new(by_name),
+            cache_capacity,
+            merge_operator,
+            manifest_path,
+            durability,
+            manifest: Mutex::new(()),
+        }
+    }
+
+    // The family called `name`, created empty if the store doesn't have it yet.
+    pub(super) fn get(&self, name: &str) -> Result<Arc<Family<K, V>>, DingoError> {
+        if let Some(family) = self.by_name.read()?.get(name) {
+            return Ok(Arc::clone(family));
+        }
+        let mut by_name = self.by_name.write()?;
+        let family = by_name
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(Family::new(name, self.cache_capacity, self.merge_operator)));
+        Ok(Arc::clone(family))
+    }
+
+    // Every family, in name order, so the default family comes first.
+    pub(super) fn all(&self) -> Result<Vec<Arc<Family<K, V>>>, DingoError> {
+        Ok(self.by_name.read()?.values().cloned().collect())
+    }
+
+    // Rewrites the manifest from every family's current SSTable list. Callers must not hold any
+    // family's flushed_files lock.
+    pub(super) fn write_manifest(&self) -> Result<(), DingoError> {
+        let _manifest = self.manifest.lock()?;
+        let mut listed = Vec::new();
+        for family in self.all()? {
+            listed.push((family.name.clone(), family.flushed_files.read()?.tables().to_vec()));
+        }
+        manifest::write(&self.manifest_path, &listed, self.durability)
+    }
+}
@@ -0,0 +1,40 @@
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{DingoError, DingoStore, Key, Value};
+
+// One line of an export.
+#[derive(Serialize, Deserialize)]
+struct JsonRecord<K, V> {
+    key: K,
+    value: V,
+}
+
+// Backups as newline-delimited JSON, one {"key": ..., "value": ...} object per live key. Only
+// stores whose keys can themselves be serialized can be exported.
+impl<K: Key + Serialize + DeserializeOwned, V: Value> DingoStore<K, V> {
+    // Writes every live pair in ascending key order, streamed the same way as range(..). Values
+    // are written as they read now: expiry times aren't carried over.
+    pub fn export_json<W: Write>(&self, w: W) -> Result<(), DingoError> {
+        let mut w = BufWriter::new(w);
+        for item in self.range(..)? {
+            let (key, value) = item?;
+            serde_json::to_writer(&mut w, &JsonRecord { key, value }).map_err(std::io::Error::from)?;
+            w.write_all(b"\n")?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    // Inserts every pair of an export, one record at a time, as insert() would. Pairs before a
+    // malformed record stay inserted.
+    pub fn import_json<R: Read>(&mut self, r: R) -> Result<(), DingoError> {
+        let records = serde_json::Deserializer::from_reader(BufReader::new(r)).into_iter::<JsonRecord<K, V>>();
+        for record in records {
+            let JsonRecord { key, value } = record.map_err(std::io::Error::from)?;
+            self.insert(key, value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use std::io::ErrorKind;
+
+use super::DingoError;
+
+// Anything the store can be keyed by. Records are kept and written out in Ord order, and reads
+// compare decoded keys rather than their bytes, so the encoding doesn't need to sort the same way.
+// Encodings must be self-delimiting: the first PREFIX_LEN bytes say how long the whole key is.
+pub trait Key: Ord + Clone + Send + Sync + 'static {
+    const PREFIX_LEN: usize;
+
+    fn encode(&self) -> Vec<u8>;
+
+    // Length of the whole encoding, given its first PREFIX_LEN bytes.
+    fn encoded_len(prefix: &[u8]) -> usize;
+
+    fn decode(bytes: &[u8]) -> Result<Self, DingoError>;
+
+    // Fed to the SSTable Bloom filters, which are persisted, so it must be stable across runs.
+    fn bloom_hash(&self) -> u64;
+}
+
+// Fixed 8 bytes, big-endian.
+impl Key for u64 {
+    const PREFIX_LEN: usize = 8;
+
+    fn encode(&self) -> Vec<u8> {
+        self.to_be_bytes().to_vec()
+    }
+
+    fn encoded_len(_: &[u8]) -> usize {
+        8
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DingoError> {
+        Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn bloom_hash(&self) -> u64 {
+        *self
+    }
+}
+
+// A u32 big-endian byte length followed by the UTF-8 bytes.
+impl Key for String {
+    const PREFIX_LEN: usize = 4;
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + self.len());
+        bytes.extend_from_slice(&(self.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(self.as_bytes());
+        bytes
+    }
+
+    fn encoded_len(prefix: &[u8]) -> usize {
+        4 + u32::from_be_bytes(prefix.try_into().unwrap()) as usize
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DingoError> {
+        String::from_utf8(bytes[4..].to_vec())
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e).into())
+    }
+
+    // FNV-1a over the UTF-8 bytes.
+    fn bloom_hash(&self) -> u64 {
+        self.bytes().fold(0xCBF2_9CE4_8422_2325, |hash, b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01B3))
+    }
+}
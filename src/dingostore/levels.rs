@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+use super::tombstone::{self, RangeTombstone};
+use super::{table, DingoError, Key};
+
+// Each level from 2 down may hold LEVEL_FANOUT times as much as the one above it.
+const LEVEL_FANOUT: u64 = 10;
+
+// How the store merges its SSTables as they pile up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompactionStyle {
+    // Once there are more than compaction_trigger SSTables, all of them are merged into one.
+    // Reads check few files, but every compaction rewrites the whole store.
+    SizeTiered,
+    // Once there are more than compaction_trigger SSTables, each run of adjacent tables that adds
+    // up to less than twice memtable_size_bytes is merged into one table, and larger tables are
+    // left alone. Compactions stay cheap however big the store grows, but reads may check more
+    // files than with SizeTiered.
+    SmallRuns,
+    // Flushed SSTables land in level 0. Once there are more than compaction_trigger of them
+    // they're merged into level 1, and once a deeper level outgrows its size limit one of its
+    // tables is merged into the next. Levels 1 and down are runs of non-overlapping tables, so a
+    // compaction only rewrites the handful of tables its input overlaps, and a lookup checks at
+    // most one table per level below 0.
+    Leveled,
+}
+
+// What the store knows about one of its SSTables.
+#[derive(Clone)]
+pub(super) struct TableMeta<K> {
+    pub(super) level: usize,
+    // The first and last keys cover the table's range tombstones as well as its records.
+    pub(super) firstkey: K,
+    pub(super) lastkey: K,
+    pub(super) filename: String,
+    // Size of the file in bytes.
+    pub(super) size: u64,
+    // Ranges deleted while the table's records were in a memtable, or carried over from the
+    // tables compacted into it. They shadow older tables only, not the table's own records. Unset
+    // for the tables the store was opened with until they're first needed; see range_tombstones().
+    pub(super) range_tombstones: Arc<OnceLock<Vec<RangeTombstone<K>>>>,
+}
+
+impl<K: Key> TableMeta<K> {
+    // Whether the key falls between the table's first and last keys. A lookup for a key outside
+    // them can skip the table without checking its filter.
+    pub(super) fn spans(&self, key: &K) -> bool {
+        self.firstkey <= *key && *key <= self.lastkey
+    }
+
+    // The table's range tombstones, read from the start of the file the first time they're asked
+    // for.
+    pub(super) fn range_tombstones(&self) -> Result<&[RangeTombstone<K>], DingoError> {
+        if let Some(tombstones) = self.range_tombstones.get() {
+            return Ok(tombstones);
+        }
+        let tombstones = table::range_tombstones(&self.filename)?;
+        Ok(self.range_tombstones.get_or_init(|| tombstones))
+    }
+
+    // Whether one of the table's range tombstones covers the key.
+    pub(super) fn deletes(&self, key: &K) -> Result<bool, DingoError> {
+        Ok(tombstone::deleted(self.range_tombstones()?, key))
+    }
+
+    fn overlaps(&self, first: &K, last: &K) -> bool {
+        self.firstkey <= *last && self.lastkey >= *first
+    }
+}
+
+// Tables to merge into `level`: `inputs` are in lookup order, so the newest record of a key is
+// in the last input holding it. `bottom` is set when no deeper level has tables, so nothing
+// older is left for tombstones and expired values to shadow.
+pub(super) struct Compaction<K> {
+    pub(super) level: usize,
+    pub(super) inputs: Vec<TableMeta<K>>,
+    pub(super) bottom: bool,
+}
+
+// The SSTables by level, kept as one list in lookup order: oldest data first, so a table shadows
+// every table before it. Deeper levels hold older data and come first, each level ordered by
+// key, with level 0 last, oldest first. Size-tiered compaction keeps everything in level 0.
+pub(super) struct Levels<K> {
+    tables: Vec<TableMeta<K>>,
+    // Per level, the last key of the table it most recently compacted, so compactions take
+    // turns over the key space.
+    cursors: HashMap<usize, K>,
+}
+
+impl<K: Key> Levels<K> {
+    pub(super) fn new() -> Levels<K> {
+        Levels { tables: Vec::new(), cursors: HashMap::new() }
+    }
+
+    // Expects `tables` in lookup order, as they were listed in the manifest.
+    pub(super) fn from_tables(tables: Vec<TableMeta<K>>) -> Levels<K> {
+        Levels { tables, cursors: HashMap::new() }
+    }
+
+    // Every table in lookup order.
+    pub(super) fn tables(&self) -> &[TableMeta<K>] {
+        &self.tables
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    pub(super) fn level(&self, level: usize) -> &[TableMeta<K>] {
+        let start = self.tables.partition_point(|table| table.level > level);
+        let end = self.tables.partition_point(|table| table.level >= level);
+        &self.tables[start..end]
+    }
+
+    fn deepest(&self) -> usize {
+        self.tables.first().map_or(0, |table| table.level)
+    }
+
+    // A freshly flushed table, newer than everything else.
+    pub(super) fn push(&mut self, table: TableMeta<K>) {
+        self.tables.push(table);
+    }
+
+    // Swaps the tables named in `removed` for `added`, keeping lookup order.
+    pub(super) fn replace(&mut self, removed: &[TableMeta<K>], added: Vec<TableMeta<K>>) {
+        self.tables.retain(|table| !removed.iter().any(|old| old.filename == table.filename));
+        self.tables.extend(added);
+        // Stable, so level 0 keeps its age order.
+        self.tables.sort_by(|a, b| {
+            b.level.cmp(&a.level).then_with(|| match a.level {
+                0 => std::cmp::Ordering::Equal,
+                _ => a.firstkey.cmp(&b.firstkey),
+            })
+        });
+    }
+
+    // Runs of two or more adjacent level 0 tables, in lookup order, that add up to less than
+    // `target` bytes. Each run is as long as it can be, and tables of `target` bytes or more are
+    // never part of one.
+    pub(super) fn small_runs(&self, target: u64) -> Vec<Vec<TableMeta<K>>> {
+        let mut runs = Vec::new();
+        let mut run: Vec<TableMeta<K>> = Vec::new();
+        let mut run_size = 0;
+        for table in self.level(0) {
+            if run_size + table.size >= target {
+                if run.len() > 1 {
+                    runs.push(std::mem::take(&mut run));
+                }
+                run.clear();
+                run_size = 0;
+            }
+            if table.size < target {
+                run_size += table.size;
+                run.push(table.clone());
+            }
+        }
+        if run.len() > 1 {
+            runs.push(run);
+        }
+        runs
+    }
+
+    // Swaps a run of adjacent tables for the table they were merged into, if any, in the run's
+    // place in lookup order.
+    pub(super) fn replace_run(&mut self, run: &[TableMeta<K>], merged: Option<TableMeta<K>>) {
+        if let Some(pos) = self.tables.iter().position(|table| table.filename == run[0].filename) {
+            self.tables.splice(pos..pos + run.len(), merged);
+        }
+    }
+
+    // The next leveled compaction due, if any. Level 0 is compacted once it has more than
+    // `trigger` tables; level n from 1 down once it holds more than table_bytes * 10^n bytes.
+    pub(super) fn pick_compaction(&mut self, trigger: usize, table_bytes: u64) -> Option<Compaction<K>> {
+        let level0 = self.level(0);
+        let (source, first, last) = if level0.len() > trigger {
+            let first = level0.iter().map(|table| &table.firstkey).min()?.clone();
+            let last = level0.iter().map(|table| &table.lastkey).max()?.clone();
+            (0, first, last)
+        } else {
+            let mut limit = table_bytes;
+            let source = (1..=self.deepest()).find(|level| {
+                limit = limit.saturating_mul(LEVEL_FANOUT);
+                self.level(*level).iter().map(|table| table.size).sum::<u64>() > limit
+            })?;
+            let tables = self.level(source);
+            let picked = self
+                .cursors
+                .get(&source)
+                .and_then(|cursor| tables.iter().find(|table| table.firstkey > *cursor))
+                .unwrap_or(&tables[0]);
+            (source, picked.firstkey.clone(), picked.lastkey.clone())
+        };
+        self.cursors.insert(source, last.clone());
+
+        let inputs = self
+            .tables
+            .iter()
+            .filter(|table| match table.level {
+                level if level == source => source == 0 || table.firstkey == first,
+                level => level == source + 1 && table.overlaps(&first, &last),
+            })
+            .cloned()
+            .collect();
+        Some(Compaction { level: source + 1, inputs, bottom: self.deepest() <= source + 1 })
+    }
+}
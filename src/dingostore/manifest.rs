@@ -0,0 +1,118 @@
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use super::{DingoError, Durability, Key, TableMeta};
+
+// Every manifest starts with this, then the format version (u8).
+const MAGIC: u32 = 0xD1E6_3A4F;
+const HEADER_LEN: usize = 5;
+// The format version written, and the only one read.
+const FORMAT_VERSION: u8 = 1;
+
+// A table as the manifest lists it.
+pub(super) struct Listed<K> {
+    pub(super) family: String,
+    pub(super) level: usize,
+    pub(super) firstkey: K,
+    pub(super) lastkey: K,
+    pub(super) filename: String,
+}
+
+// The manifest lists the live SSTables of every column family, each family's in lookup order
+// (see Levels): MAGIC and FORMAT_VERSION, the table count (u32), then per table the family
+// name's length (u32) and the name, its level (u32), encoded first and last keys, and the file
+// name's length (u32) and the file name, relative to the manifest's directory. A CRC32 of
+// everything before it closes the file.
+//
+// It's replaced wholesale by writing a temporary file and renaming it over the old one, so a
+// crash leaves either the old or the new list, never a mix. SSTables only become part of the
+// store once the manifest names them.
+pub(super) fn write<K: Key>(path: &str, families: &[(String, Vec<TableMeta<K>>)], durability: Durability) -> Result<(), DingoError> {
+    let count: usize = families.iter().map(|(_, tables)| tables.len()).sum();
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&MAGIC.to_be_bytes());
+    bytes.push(FORMAT_VERSION);
+    bytes.extend_from_slice(&(count as u32).to_be_bytes());
+    for (family, tables) in families {
+        for table in tables {
+            let name = Path::new(&table.filename).file_name().unwrap_or_default().to_string_lossy();
+            bytes.extend_from_slice(&(family.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(family.as_bytes());
+            bytes.extend_from_slice(&(table.level as u32).to_be_bytes());
+            bytes.extend_from_slice(&table.firstkey.encode());
+            bytes.extend_from_slice(&table.lastkey.encode());
+            bytes.extend_from_slice(&(name.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(name.as_bytes());
+        }
+    }
+    bytes.extend_from_slice(&crc32fast::hash(&bytes).to_be_bytes());
+
+    let tmp_path = format!("{}.tmp", path);
+    let mut tmp = BufWriter::new(File::create(&tmp_path)?);
+    tmp.write_all(&bytes)?;
+    let tmp = tmp.into_inner().map_err(|e| e.into_error())?;
+    if durability.sync_files() {
+        tmp.sync_all()?;
+    }
+    std::fs::rename(&tmp_path, path)?;
+    durability.sync_dir_of(path)?;
+    Ok(())
+}
+
+// None if there's no manifest yet. File names come back joined onto `dir`. Manifests of a format
+// version other than FORMAT_VERSION are refused.
+pub(super) fn read<K: Key>(path: &str, dir: &Path) -> Result<Option<Vec<Listed<K>>>, DingoError> {
+    let mut bytes = Vec::new();
+    match File::open(path) {
+        Ok(mut f) => f.read_to_end(&mut bytes)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let corrupt = |offset: usize| DingoError::Corruption { file: path.to_string(), offset: offset as u64 };
+    if bytes.len() < HEADER_LEN + 8 || u32::from_be_bytes(bytes[..4].try_into().unwrap()) != MAGIC {
+        return Err(corrupt(0));
+    }
+    if bytes[4] != FORMAT_VERSION {
+        return Err(DingoError::UnsupportedVersion { file: path.to_string(), version: bytes[4] });
+    }
+    let (body, crc) = bytes.split_at(bytes.len() - 4);
+    if u32::from_be_bytes(crc.try_into().unwrap()) != crc32fast::hash(body) {
+        return Err(corrupt(body.len()));
+    }
+
+    let read_u32 = |pos: usize| {
+        body.get(pos..pos + 4)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(|| corrupt(pos))
+    };
+    let read_key = |pos: usize| {
+        let key_len = body.get(pos..pos + K::PREFIX_LEN).map_or(usize::MAX, K::encoded_len);
+        let key_bytes = body.get(pos..pos.saturating_add(key_len)).ok_or_else(|| corrupt(pos))?;
+        Ok::<_, DingoError>((K::decode(key_bytes)?, key_len))
+    };
+    // A length-prefixed name, and how many bytes it took up.
+    let read_name = |pos: usize| {
+        let len = read_u32(pos)? as usize;
+        let name = body.get(pos + 4..pos + 4 + len).ok_or_else(|| corrupt(pos))?;
+        Ok::<_, DingoError>((String::from_utf8_lossy(name).into_owned(), 4 + len))
+    };
+    let count = read_u32(HEADER_LEN)?;
+    let mut pos = HEADER_LEN + 4;
+    let mut tables = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let (family, len) = read_name(pos)?;
+        pos += len;
+        let level = read_u32(pos)? as usize;
+        pos += 4;
+        let (firstkey, key_len) = read_key(pos)?;
+        pos += key_len;
+        let (lastkey, key_len) = read_key(pos)?;
+        pos += key_len;
+        let (name, len) = read_name(pos)?;
+        pos += len;
+        let filename = dir.join(name).to_string_lossy().to_string();
+        tables.push(Listed { family, level, firstkey, lastkey, filename });
+    }
+    Ok(Some(tables))
+}
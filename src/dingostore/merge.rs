@@ -0,0 +1,183 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::BufReader;
+use std::ops::Bound;
+
+use super::table::ReverseReader;
+use super::tombstone::{self, RangeTombstone};
+use super::{DingoError, Entry, Key, MergeOperator, RecordReader, Value};
+
+// A sorted stream of records, ascending unless it's a TableRev. Memtable slices for a descending
+// merge are collected in descending order.
+pub(super) enum Source<K, V> {
+    Mem(std::vec::IntoIter<(K, Entry<V>)>),
+    Table(RecordReader<BufReader<File>>),
+    TableRev(ReverseReader<K>),
+}
+
+// A source's head record's key on the heap. The heap pops the key that comes first in the
+// merge's direction, and on ties the newest source first.
+struct Head<K> {
+    key: K,
+    idx: usize,
+    descending: bool,
+}
+
+impl<K: Ord> Ord for Head<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let by_key = if self.descending { self.key.cmp(&other.key) } else { other.key.cmp(&self.key) };
+        by_key.then(self.idx.cmp(&other.idx))
+    }
+}
+
+impl<K: Ord> PartialOrd for Head<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord> PartialEq for Head<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K: Ord> Eq for Head<K> {}
+
+// Lazily merges sorted sources into one ascending (or descending) stream with a k-way merge.
+// Sources are given oldest first; when a key shows up in several of them, only the newest record
+// is yielded, with any pending merges on top of it laid over it. A record covered by a range
+// tombstone of a newer source comes through as a tombstone. Tombstones and expired values come
+// through as they are so callers can decide whether to drop them.
+pub(super) struct MergeIter<K: Key, V: Value> {
+    sources: Vec<Source<K, V>>,
+    // Each source's range tombstones, in the same order as the sources.
+    range_tombstones: Vec<Vec<RangeTombstone<K>>>,
+    heads: Vec<Option<Entry<V>>>,
+    heap: BinaryHeap<Head<K>>,
+    start: Bound<K>,
+    end: Bound<K>,
+    // Set when the sources, and so the merge, run from the end bound down to the start bound.
+    descending: bool,
+    lastkey: Option<K>,
+    failed: bool,
+    merge_operator: Option<MergeOperator<V>>,
+    // Set when nothing older than the sources can hold their keys, so merges still pending once
+    // every source has been consulted are applied to no value at all.
+    bottom: bool,
+}
+
+impl<K: Key, V: Value> MergeIter<K, V> {
+    pub(super) fn new(
+        sources: Vec<Source<K, V>>,
+        range_tombstones: Vec<Vec<RangeTombstone<K>>>,
+        start: Bound<K>,
+        end: Bound<K>,
+        descending: bool,
+        merge_operator: Option<MergeOperator<V>>,
+        bottom: bool,
+    ) -> Result<MergeIter<K, V>, DingoError> {
+        let mut iter = MergeIter {
+            heads: (0..sources.len()).map(|_| None).collect(),
+            sources,
+            range_tombstones,
+            heap: BinaryHeap::new(),
+            start,
+            end,
+            descending,
+            lastkey: None,
+            failed: false,
+            merge_operator,
+            bottom,
+        };
+        for idx in 0..iter.sources.len() {
+            iter.advance(idx)?;
+        }
+        Ok(iter)
+    }
+
+    // Pulls the next in-bounds record from a source onto the heap. Tables may be positioned a
+    // little before the bound they start from, so leading records are skipped.
+    fn advance(&mut self, idx: usize) -> Result<(), DingoError> {
+        loop {
+            let next = match &mut self.sources[idx] {
+                Source::Mem(records) => records.next(),
+                Source::Table(reader) => reader.try_deserialize()?,
+                Source::TableRev(reader) => reader.try_deserialize()?,
+            };
+            let Some((key, val)) = next else {
+                return Ok(());
+            };
+            let below_start = match &self.start {
+                Bound::Included(s) => key < *s,
+                Bound::Excluded(s) => key <= *s,
+                Bound::Unbounded => false,
+            };
+            let past_end = match &self.end {
+                Bound::Included(e) => key > *e,
+                Bound::Excluded(e) => key >= *e,
+                Bound::Unbounded => false,
+            };
+            let (skip, done) = if self.descending { (past_end, below_start) } else { (below_start, past_end) };
+            if skip {
+                continue;
+            }
+            if done {
+                return Ok(());
+            }
+            self.heap.push(Head { key, idx, descending: self.descending });
+            self.heads[idx] = Some(val);
+            return Ok(());
+        }
+    }
+
+    // Pops the head of a source off the heap, moving the source on to its next record. The
+    // record comes back as a tombstone if a newer source deleted a range covering it.
+    fn pop(&mut self) -> Result<Option<(K, Entry<V>)>, DingoError> {
+        let Some(Head { key, idx, .. }) = self.heap.pop() else {
+            return Ok(None);
+        };
+        let mut val = self.heads[idx].take().unwrap();
+        self.advance(idx)?;
+        if self.range_tombstones[idx + 1..].iter().any(|tombstones| tombstone::deleted(tombstones, &key)) {
+            val = Entry::new(None);
+        }
+        Ok(Some((key, val)))
+    }
+
+    fn next_entry(&mut self) -> Result<Option<(K, Entry<V>)>, DingoError> {
+        while let Some((key, mut val)) = self.pop()? {
+            // Anything else with this key came from an older source and is shadowed.
+            if self.lastkey.as_ref() == Some(&key) {
+                continue;
+            }
+            self.lastkey = Some(key.clone());
+            while val.is_merge() && self.heap.peek().is_some_and(|next| next.key == key) {
+                let (_, older) = self.pop()?.unwrap();
+                val = val.over(older, self.merge_operator)?;
+            }
+            // Merges still pending with a range tombstone under them apply to no value, as they
+            // would at the bottom.
+            let deleted = || self.range_tombstones.iter().any(|tombstones| tombstone::deleted(tombstones, &key));
+            if self.bottom || (val.is_merge() && deleted()) {
+                val = val.over(Entry::new(None), self.merge_operator)?;
+            }
+            return Ok(Some((key, val)));
+        }
+        Ok(None)
+    }
+}
+
+impl<K: Key, V: Value> Iterator for MergeIter<K, V> {
+    type Item = Result<(K, Entry<V>), DingoError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let next = self.next_entry();
+        self.failed = next.is_err();
+        next.transpose()
+    }
+}
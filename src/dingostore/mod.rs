@@ -1,142 +1,1594 @@
-use std::{collections::BTreeMap, time::{SystemTime, UNIX_EPOCH}};
-use std::mem::size_of_val;
-use std::fs::OpenOptions;
-use std::io::{Write, Read};
-use std::sync::{Arc, Mutex};
+use std::{collections::BTreeMap, time::{Duration, SystemTime, UNIX_EPOCH}};
+use std::fs::{File, OpenOptions};
+use std::io::{Write, BufReader, BufWriter, ErrorKind, Seek, SeekFrom};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use serde::{de::DeserializeOwned, Serialize};
 
+mod bloom;
+mod builder;
+mod cache;
+mod compression;
+mod durability;
+mod error;
+mod family;
+mod json;
+mod key;
+mod levels;
+mod manifest;
+mod merge;
+mod operator;
+mod snapshot;
+mod stats;
+mod table;
+mod tombstone;
+use bloom::Bloom;
+use cache::Lru;
+use family::{Families, Family};
+pub use builder::DingoStoreBuilder;
+pub use compression::Compression;
+pub use durability::Durability;
+pub use error::DingoError;
+pub use key::Key;
+pub use levels::CompactionStyle;
+pub use operator::{add_integers, MergeOperator};
+pub use snapshot::Snapshot;
+pub use stats::DingoStats;
+use stats::Counters;
+use levels::{Levels, TableMeta};
+use merge::{MergeIter, Source};
+use table::{RawRecord, RecordReader, TableWriter, Tables};
+use tombstone::RangeTombstone;
+
+// Defaults for DingoStoreBuilder::memtable_size_bytes and compaction_trigger.
 const SIZE_THRESH: u32 = 80000;
+const COMPACT_LIM: usize = 10;
+// Default for DingoStoreBuilder::block_size, a common SSD page size.
+const BLOCK_SIZE: u32 = 4096;
+// A value length of u32::MAX marks a deleted key.
+const TOMBSTONE: u32 = u32::MAX;
+// A value length of u32::MAX - 1 marks the start of a WAL batch. Its key is the batch's first
+// key and its payload is how many records follow, as a u64.
+const BATCH: u32 = u32::MAX - 1;
+// A value length of u32::MAX - 2 marks a value with an expiry: the value's expiry time (u64
+// milliseconds since the epoch) and its real length (u32) follow before the value itself.
+const EXPIRING: u32 = u32::MAX - 2;
+// A value length of u32::MAX - 3 marks a WAL record naming the column family the next record or
+// batch belongs to: the name's length (u32) and the name follow. Its key is the next record's
+// key. Records of the default family have none.
+const FAMILY: u32 = u32::MAX - 3;
+// A value length of u32::MAX - 4 marks merge operands still waiting to be applied to an older
+// value: their real length (u32) follows, then the operands, oldest first, as a bincode list of
+// each operand's own bincode encoding.
+const MERGE: u32 = u32::MAX - 4;
+// A value length of u32::MAX - 5 marks a WAL record deleting a range of keys: the encoded range
+// tombstone's length (u32) and the tombstone follow. Its key is the range's start.
+const RANGE_DELETE: u32 = u32::MAX - 5;
+
+// Anything that can be stored as a value: values are kept as their bincode encoding, and
+// memtables are handed to a background thread to be flushed. Implemented for every type that
+// meets the bounds.
+pub trait Value: Serialize + DeserializeOwned + Clone + Send + Sync + 'static {}
+
+impl<T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static> Value for T {}
+
+// What a key maps to in a memtable or on disk: its value, None for a tombstone, and when the value
+// expires, in milliseconds since the epoch. 0 means it never does. An entry with operands is a
+// pending merge instead: they're applied, oldest first, to whatever older value the key has.
+#[derive(Clone)]
+struct Entry<V> {
+    val: Option<V>,
+    expires: u64,
+    operands: Vec<V>,
+}
+
+impl<V> Entry<V> {
+    fn new(val: Option<V>) -> Entry<V> {
+        Entry { val, expires: 0, operands: Vec::new() }
+    }
+
+    fn merge(operand: V) -> Entry<V> {
+        Entry { val: None, expires: 0, operands: vec![operand] }
+    }
+
+    fn is_merge(&self) -> bool {
+        !self.operands.is_empty()
+    }
+
+    // Whether the key reads as present. A pending merge always leaves a value behind.
+    fn present(&self) -> bool {
+        self.is_merge() || self.live().is_some()
+    }
+
+    fn expired(&self) -> bool {
+        self.expires != 0 && self.expires <= now_millis()
+    }
+
+    // The value as reads should see it now: an expired value reads as deleted, and still
+    // shadows anything older for the key.
+    fn live(&self) -> Option<&V> {
+        if self.expired() {
+            return None;
+        }
+        self.val.as_ref()
+    }
+
+    fn into_live(self) -> Option<V> {
+        if self.expired() {
+            return None;
+        }
+        self.val
+    }
+
+    // Lays this entry over an older one for the same key. A pending merge applies its operands to
+    // the older value, or if that's pending too, joins its operands; anything else shadows it.
+    fn over(self, older: Entry<V>, operator: Option<MergeOperator<V>>) -> Result<Entry<V>, DingoError> {
+        if !self.is_merge() {
+            return Ok(self);
+        }
+        if older.is_merge() {
+            let mut older = older;
+            older.operands.extend(self.operands);
+            return Ok(older);
+        }
+        let operator = operator.ok_or(DingoError::NoMergeOperator)?;
+        let mut val = older.into_live();
+        for operand in &self.operands {
+            val = Some(operator(val.as_ref(), operand));
+        }
+        Ok(Entry::new(val))
+    }
+}
+
+impl Entry<Vec<u8>> {
+    // Decodes the value and operands of `key`'s entry, read from `file`.
+    fn decode<K: Key, V: Value>(self, key: &K, file: &str) -> Result<Entry<V>, DingoError> {
+        let val = self.val.map(|bytes| decode_value(&bytes, key, file)).transpose()?;
+        let operands = self.operands.iter().map(|bytes| decode_value(bytes, key, file)).collect::<Result<_, _>>()?;
+        Ok(Entry { val, expires: self.expires, operands })
+    }
+}
+
+// Decodes a value of `key` read from `file`. A string that isn't valid UTF-8 is reported as
+// such, with where it was found, rather than as a bare codec error.
+fn decode_value<K: Key, V: Value>(bytes: &[u8], key: &K, file: &str) -> Result<V, DingoError> {
+    bincode::deserialize(bytes).map_err(|e| match *e {
+        bincode::ErrorKind::InvalidUtf8Encoding(_) => DingoError::InvalidUtf8 { key: key.encode(), file: file.to_string() },
+        _ => DingoError::Codec(e),
+    })
+}
+
+// Lays the pending merges found for a key, newest first, over the older entry they sit on.
+fn stack<V>(merges: Vec<Entry<V>>, older: Entry<V>, operator: Option<MergeOperator<V>>) -> Result<Entry<V>, DingoError> {
+    merges.into_iter().rev().try_fold(older, |older, entry| entry.over(older, operator))
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+// A memtable's entries, and the ranges deleted while it was the current one. Deleting a range
+// drops the entries it covers, so every entry is newer than the range tombstones.
+#[derive(Clone)]
+struct Memtable<K, V> {
+    entries: BTreeMap<K, Entry<V>>,
+    range_tombstones: Vec<RangeTombstone<K>>,
+}
+
+impl<K, V> Default for Memtable<K, V> {
+    fn default() -> Memtable<K, V> {
+        Memtable { entries: BTreeMap::new(), range_tombstones: Vec::new() }
+    }
+}
+
+impl<K: Key, V: Clone> Memtable<K, V> {
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.range_tombstones.is_empty()
+    }
+
+    // What the memtable has to say about a key: its entry, a tombstone if one of the ranges
+    // deleted covers it, or None to leave it to older data.
+    fn lookup(&self, key: &K) -> Option<Entry<V>> {
+        match self.entries.get(key) {
+            Some(entry) => Some(entry.clone()),
+            None => tombstone::deleted(&self.range_tombstones, key).then(|| Entry::new(None)),
+        }
+    }
+}
+
+// A memtable handed off to be flushed, shared with the thread writing it.
+type Frozen<K, V> = Option<Arc<Memtable<K, V>>>;
+
+// A write replayed from the WAL, along with the name of the family it belongs to.
+enum Logged<K, V> {
+    Entry(String, K, Entry<V>),
+    RangeDelete(String, RangeTombstone<K>),
+}
 
+// The payload after the length prefix is opaque bincode bytes.
+fn serialize<K: Key, V: Serialize>(key: &K, val: Option<&V>, expires: u64) -> Result<Vec<u8>, DingoError> {
+    let payload = val.map(bincode::serialize).transpose()?;
+    Ok(encode_record(key, payload.as_deref(), expires))
+}
 
-pub struct DingoStore<'a> {
-    objs: Mutex<BTreeMap<u64, String>>,
-    fname: &'a str,
-    treesize: u32,
-    flushed_files: Mutex<BTreeMap<u64, String>>,
+// Like serialize, for any entry, pending merges included.
+fn serialize_entry<K: Key, V: Serialize>(key: &K, entry: &Entry<V>) -> Result<Vec<u8>, DingoError> {
+    if entry.is_merge() {
+        return Ok(encode_merge(key, &operands_payload(&entry.operands)?));
+    }
+    serialize(key, entry.val.as_ref(), entry.expires)
 }
 
-impl<'a> DingoStore<'a> {
-    pub fn new(fname: &'a str) -> DingoStore<'a> {
-        DingoStore {
-            fname, 
-            objs: Mutex::new(BTreeMap::new()),
-            treesize: 0,
-            flushed_files: Mutex::new(BTreeMap::new()),
-        } 
+// A None payload is written as a tombstone. Values that never expire (expires is 0) are written
+// without an expiry, as they were before expiring values existed.
+fn encode_record<K: Key>(key: &K, payload: Option<&[u8]>, expires: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    
+    bytes.extend_from_slice(&key.encode());
+    match payload {
+        Some(payload) => {
+            if expires != 0 {
+                bytes.extend_from_slice(&EXPIRING.to_be_bytes());
+                bytes.extend_from_slice(&expires.to_be_bytes());
+            }
+            bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(payload);
+        }
+        None => bytes.extend_from_slice(&TOMBSTONE.to_be_bytes()),
     }
     
-    pub fn insert(&mut self, key: u64, val: String, ) -> (u64, String) {
-        let new_size = self.treesize + std::mem::size_of::<u64>() as u32 + size_of_val(&val) as u32;
-        if new_size > SIZE_THRESH  {
-            self.flush();
-            let mut objs = self.objs.lock().unwrap(); 
-            objs.insert(key, val.clone());
-            self.treesize = std::mem::size_of::<u64>() as u32 + size_of_val(&val) as u32;
-        } else {
+    bytes
+}
+
+fn encode_merge<K: Key>(key: &K, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = key.encode();
+    bytes.extend_from_slice(&MERGE.to_be_bytes());
+    bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+fn encode_range_delete<K: Key>(tombstone: &RangeTombstone<K>) -> Vec<u8> {
+    let payload = tombstone.encode();
+    let mut bytes = tombstone.start.encode();
+    bytes.extend_from_slice(&RANGE_DELETE.to_be_bytes());
+    bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+fn operands_payload<V: Serialize>(operands: &[V]) -> Result<Vec<u8>, DingoError> {
+    let encoded = operands.iter().map(bincode::serialize).collect::<Result<Vec<_>, _>>()?;
+    Ok(bincode::serialize(&encoded)?)
+}
+
+fn family_marker<K: Key>(key: &K, name: &str) -> Vec<u8> {
+    let mut bytes = key.encode();
+    bytes.extend_from_slice(&FAMILY.to_be_bytes());
+    bytes.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(name.as_bytes());
+    bytes
+}
+
+// Bytes a value takes up once serialized: its length prefix, expiry if it has one, plus the
+// encoded value. Together with the key this is exactly the record's footprint in the WAL and
+// SSTables.
+fn value_size<V: Value>(val: Option<&V>, expires: u64) -> Result<u32, DingoError> {
+    let payload = match val {
+        Some(val) if expires != 0 => 12 + bincode::serialized_size(val)? as u32,
+        Some(val) => bincode::serialized_size(val)? as u32,
+        None => 0,
+    };
+    Ok(std::mem::size_of::<u32>() as u32 + payload)
+}
+
+// Like value_size, for any entry: pending merges take up the merge marker and length, plus their
+// operands.
+fn entry_size<V: Value>(entry: &Entry<V>) -> Result<u32, DingoError> {
+    if entry.is_merge() {
+        return Ok(8 + operands_payload(&entry.operands)?.len() as u32);
+    }
+    value_size(entry.val.as_ref(), entry.expires)
+}
+
+fn write_memtable<K: Key, V: Value>(mut writer: TableWriter<K>, memtable: &Memtable<K, V>) -> Result<(Option<(K, K)>, Bloom), DingoError> {
+    for tombstone in &memtable.range_tombstones {
+        writer.add_range_tombstone(tombstone)?;
+    }
+    for (key, entry) in memtable.entries.iter() {
+        // Write to data file and update index
+        writer.add(key, entry)?;
+    }
+    writer.finish()
+}
+
+// Keys default to u64 and values to String. Clones are handles to the same store; see the Clone
+// impl. A handle reads and writes one column family, the default one unless it came from cf().
+pub struct DingoStore<K: Key = u64, V: Value = String> {
+    inner: Arc<Inner<K, V>>,
+    family: Arc<Family<K, V>>,
+}
+
+// Everything a store's handles share.
+struct Inner<K: Key, V: Value> {
+    // Taken for the whole of every write, so writes through different handles apply to the WAL
+    // and the memtable in the same order.
+    writing: Mutex<()>,
+    // How many handles are still open, on any family; the last one to be dropped flushes the
+    // memtables.
+    handles: AtomicUsize,
+    families: Arc<Families<K, V>>,
+    flushing: Mutex<Option<JoinHandle<Result<(), DingoError>>>>,
+    // The compaction_interval thread, if there is one.
+    timer: Mutex<Option<JoinHandle<()>>>,
+    fname: String,
+    data_dir: PathBuf,
+    memtable_size: u32,
+    max_value_bytes: u32,
+    compaction_trigger: usize,
+    compaction_style: CompactionStyle,
+    // Filters and record offsets for the SSTables of every family, and which of them snapshots
+    // still hold.
+    tables: Arc<Tables<K>>,
+    counters: Arc<Counters>,
+    wal: Mutex<File>,
+    durability: Durability,
+    compression: Compression,
+    block_size: u32,
+    // Timestamp of the newest SSTable name handed out.
+    last_table_ts: AtomicU64,
+}
+
+impl<K: Key, V: Value> DingoStore<K, V> {
+    // Creates a store with the default settings, or opens the one a previous run left at `fname`.
+    pub fn new(fname: &str) -> Result<DingoStore<K, V>, DingoError> {
+        DingoStoreBuilder::new(fname).build()
+    }
+
+    // Same as new().
+    pub fn open(fname: &str) -> Result<DingoStore<K, V>, DingoError> {
+        DingoStoreBuilder::new(fname).open()
+    }
+
+    fn from_builder(builder: DingoStoreBuilder<V>) -> Result<DingoStore<K, V>, DingoError> {
+        if !builder.data_dir.as_os_str().is_empty() {
+            std::fs::create_dir_all(&builder.data_dir)?;
+        }
+        let wal_path = format!("{}.wal", builder.data_dir.join(&builder.fname).display());
+        let wal = OpenOptions::new().read(true).append(true).create(true).open(&wal_path)?;
+        builder.durability.sync_dir_of(&wal_path)?;
+        let counters = Arc::new(Counters::default());
+        let manifest_path = format!("{}.manifest", builder.data_dir.join(&builder.fname).display());
+        let families = Arc::new(Families::new(manifest_path, builder.durability, builder.cache_capacity, builder.merge_operator));
+        let family = families.get("")?;
+        let ds = DingoStore { inner: Arc::new(Inner {
+            writing: Mutex::new(()),
+            handles: AtomicUsize::new(1),
+            families,
+            fname: builder.fname,
+            data_dir: builder.data_dir,
+            flushing: Mutex::new(None),
+            timer: Mutex::new(None),
+            memtable_size: builder.memtable_size_bytes,
+            max_value_bytes: builder.max_value_bytes.unwrap_or(builder.memtable_size_bytes),
+            compaction_trigger: builder.compaction_trigger,
+            compaction_style: builder.compaction_style,
+            tables: Arc::new(Tables::new(Arc::clone(&counters), builder.mmap)),
+            counters,
+            wal: Mutex::new(wal),
+            durability: builder.durability,
+            compression: builder.compression,
+            block_size: builder.block_size,
+            last_table_ts: AtomicU64::new(0),
+        }), family };
+        ds.recover_wal()?;
+        // Before anything can flush: the first flush rewrites the manifest from the tables
+        // loaded here, and would drop any left out.
+        ds.load_tables(builder.preload_indexes)?;
+        if let Some(interval) = builder.compaction_interval {
+            ds.spawn_compaction_timer(interval)?;
+        }
+        Ok(ds)
+    }
+
+    // Calls compact_now every `interval` on a thread of its own until the store's last handle is
+    // dropped, which stops and waits for it. The thread doesn't count as a handle, and only holds
+    // on to the store while it's compacting.
+    fn spawn_compaction_timer(&self, interval: Duration) -> Result<(), DingoError> {
+        let inner = Arc::downgrade(&self.inner);
+        let timer = std::thread::spawn(move || loop {
+            std::thread::park_timeout(interval);
+            let Some(inner) = inner.upgrade() else {
+                return;
+            };
+            if inner.handles.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            if let Err(e) = inner.compact_now() {
+                inner.counters.record_error(&e);
+            }
+        });
+        *self.inner.timer.lock()? = Some(timer);
+        Ok(())
+    }
+
+    // A handle on the column family `name`, which is created if the store doesn't have it yet.
+    // Each family is a key space of its own, with its own memtable and SSTables
+    // ({fname}_{name}_{ts}.data), so keys never collide across families. All of them share the
+    // store's WAL and manifest, and a memtable filling up flushes every family at once. Names
+    // are made of ASCII letters, digits, '-' and '_'.
+    pub fn cf(&self, name: &str) -> Result<DingoStore<K, V>, DingoError> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(DingoError::InvalidFamilyName(name.to_string()));
+        }
+        let family = self.inner.families.get(name)?;
+        self.inner.handles.fetch_add(1, Ordering::SeqCst);
+        Ok(DingoStore { inner: Arc::clone(&self.inner), family })
+    }
+
+    fn wal_path(&self) -> String {
+        format!("{}.wal", self.inner.prefix())
+    }
+
+    fn manifest_path(&self) -> String {
+        format!("{}.manifest", self.inner.prefix())
+    }
+
+    // The WAL covering the memtable currently being flushed in the background.
+    fn flushing_wal_path(&self) -> String {
+        format!("{}.wal.flushing", self.inner.prefix())
+    }
+
+    // Registers the SSTables a previous run left behind, as listed in the manifest, recreating
+    // the families they belong to. SSTables the manifest doesn't name were never committed (a
+    // crash mid-flush or mid-compaction) and are ignored. Stores from before the manifest
+    // existed fall back to every {prefix}_{ts}.data file, ordered by flush timestamp so newer
+    // files still shadow older ones.
+    fn load_tables(&self, preload: bool) -> Result<(), DingoError> {
+        let prefix = self.inner.prefix();
+        let path = Path::new(&prefix);
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let name_prefix = format!("{}_", path.file_name().unwrap_or_default().to_string_lossy());
+        let table_ts = |name: &str| {
+            name.strip_prefix(&name_prefix)
+                .and_then(|rest| rest.strip_suffix(".data"))
+                .and_then(|ts| ts.parse::<u128>().ok())
+        };
+
+        let listed = match manifest::read::<K>(&self.manifest_path(), dir)? {
+            Some(listed) => listed,
+            None => {
+                let mut stamps = Vec::new();
+                for entry in std::fs::read_dir(dir)? {
+                    if let Some(ts) = table_ts(&entry?.file_name().to_string_lossy()) {
+                        stamps.push(ts);
+                    }
+                }
+                stamps.sort();
+                let mut listed = Vec::new();
+                for ts in stamps {
+                    let filename = format!("{}_{}.data", prefix, ts);
+                    let mut reader = table::open_table::<K>(&filename, None)?;
+                    if let Some((firstkey, _)) = reader.try_deserialize_key::<K>()? {
+                        let lastkey = self.inner.tables.last_key(&filename)?.unwrap_or_else(|| firstkey.clone());
+                        listed.push(manifest::Listed { family: String::new(), level: 0, firstkey, lastkey, filename });
+                    }
+                }
+                listed
+            }
+        };
 
-            let mut objs = self.objs.lock().unwrap();
-            if let Some(old_val) = objs.get(&key) {
-                self.treesize -= size_of_val(old_val) as u32;
-            } else {
-                self.treesize += std::mem::size_of::<u64>() as u32;
+        let mut tables: BTreeMap<String, Vec<TableMeta<K>>> = BTreeMap::new();
+        for manifest::Listed { family, level, firstkey, lastkey, filename } in listed {
+            let name = Path::new(&filename).file_name().unwrap_or_default().to_string_lossy();
+            if let Some(ts) = table_ts(&name) {
+                self.inner.last_table_ts.fetch_max(ts as u64, Ordering::SeqCst);
+            }
+            let size = std::fs::metadata(&filename)?.len();
+            if preload {
+                self.inner.tables.preload(&filename)?;
             }
-            self.treesize += size_of_val(&val) as u32;
-            objs.insert(key, val.clone());
+            // Read from the table once they're needed.
+            let range_tombstones = Arc::default();
+            tables.entry(family).or_default().push(TableMeta { level, firstkey, lastkey, filename, size, range_tombstones });
+        }
+        for (family, tables) in tables {
+            *self.inner.families.get(&family)?.flushed_files.write()? = Levels::from_tables(tables);
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, key: K, val: V) -> Result<(), DingoError> {
+        self.write(key, Entry::new(Some(val)))?;
+        self.inner.counters.inserts.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Like insert, but returns the value the key had before, if any, the way BTreeMap::insert
+    // does. The old value is looked up wherever it lives, SSTables included, so this costs a get
+    // on top of the insert. No write through another handle can land between the two.
+    pub fn insert_returning_old(&mut self, key: K, val: V) -> Result<Option<V>, DingoError> {
+        self.check_value_size(&key, &val)?;
+        let _writing = self.inner.writing.lock()?;
+        let old = self.find(key.clone(), false)?;
+        self.write_locked(key, Entry::new(Some(val)))?;
+        self.inner.counters.inserts.fetch_add(1, Ordering::Relaxed);
+        Ok(old)
+    }
+
+    // Like insert, but the key reads as absent once `ttl` has passed. The expired value is only
+    // dropped from disk when compaction next rewrites the SSTable holding it.
+    pub fn insert_with_ttl(&mut self, key: K, val: V, ttl: Duration) -> Result<(), DingoError> {
+        let expires = now_millis().saturating_add(ttl.as_millis() as u64);
+        self.write(key, Entry { val: Some(val), expires, operands: Vec::new() })?;
+        self.inner.counters.inserts.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Leaves a tombstone rather than removing the key outright, so the deletion also shadows any
+    // value for the key that has already been flushed to an SSTable.
+    pub fn delete(&mut self, key: K) -> Result<(), DingoError> {
+        self.write(key, Entry::new(None))?;
+        self.inner.counters.deletes.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Deletes every key in `range` with a single range tombstone rather than one tombstone per
+    // key. Reads, range scans and compaction treat every key it covers as deleted until the key
+    // is written again. An unbounded end stops at the family's first or last key as of the call,
+    // so keys written beyond it later are unaffected.
+    pub fn delete_range(&mut self, range: impl RangeBounds<K>) -> Result<(), DingoError> {
+        // Taken before the bounds are worked out, so no write lands past them in the meantime.
+        let _writing = self.inner.writing.lock()?;
+        let first = match range.start_bound() {
+            Bound::Unbounded => self.keys()?.next().transpose()?,
+            _ => None,
+        };
+        let last = match range.end_bound() {
+            Bound::Unbounded => self.range_rev(..)?.next().transpose()?.map(|(key, _)| key),
+            _ => None,
+        };
+        let Some(tombstone) = tombstone::bounded(range, first, last) else {
+            return Ok(());
+        };
+        if tombstone.is_empty() {
+            return Ok(());
+        }
+        let bytes = encode_range_delete(&tombstone);
+        if self.family.treesize.load(Ordering::SeqCst) as u64 + bytes.len() as u64 > self.inner.memtable_size as u64 {
+            self.start_flush(true)?;
         }
-        (key, val)
+        self.append_wal_bytes(&tombstone.start, bytes)?;
+        self.family.delete_range(tombstone)?;
+        self.inner.counters.deletes.fetch_add(1, Ordering::Relaxed);
+        Ok(())
     }
 
+    // Stages `operand` to be folded into the key's value by the store's merge operator (see
+    // DingoStoreBuilder::merge_operator), without reading the value first. An operand merged into
+    // a value still in the memtable is applied straight away; otherwise it's kept as it is, and
+    // applied when the key is read or when compaction brings it together with the value it
+    // applies to. The merged value never expires.
+    pub fn merge(&mut self, key: K, operand: V) -> Result<(), DingoError> {
+        if self.family.merge_operator.is_none() {
+            return Err(DingoError::NoMergeOperator);
+        }
+        self.write(key, Entry::merge(operand))?;
+        self.inner.counters.merges.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Applies every pair as one unit: the memtable is flushed at most once, up front, if the
+    // batch as a whole would overflow it, and the WAL gets the batch as a single group so
+    // recovery replays either all of it or none of it.
+    pub fn insert_batch(&mut self, pairs: Vec<(K, V)>) -> Result<(), DingoError> {
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        for (key, val) in &pairs {
+            self.check_value_size(key, val)?;
+        }
+        let _writing = self.inner.writing.lock()?;
+        let batch_size: u64 = pairs
+            .iter()
+            .map(|(key, val)| Ok(key.encode().len() as u64 + value_size(Some(val), 0)? as u64))
+            .sum::<Result<u64, DingoError>>()?;
+        if self.family.treesize.load(Ordering::SeqCst) as u64 + batch_size > self.inner.memtable_size as u64 {
+            self.start_flush(true)?;
+        }
 
-    fn serialize(&self, key: u64, val: &str) -> Vec<u8> {
         let mut bytes = Vec::new();
-        let val_bytes = val.as_bytes();
-        
-        bytes.extend_from_slice(&key.to_be_bytes());
-        bytes.extend_from_slice(&(val_bytes.len() as u32).to_be_bytes());
-        bytes.extend_from_slice(val_bytes);
-        
-        bytes
-    }
-    fn seek_key(&self, filename: &String, key: u64) -> Option<Vec<u8>>{
-        let mut f = std::io::BufReader::new(std::fs::File::open(filename).unwrap()); 
-        let mut tempbuffer = [0u8; 8];
+        bytes.extend_from_slice(&pairs[0].0.encode());
+        bytes.extend_from_slice(&BATCH.to_be_bytes());
+        bytes.extend_from_slice(&(pairs.len() as u64).to_be_bytes());
+        for (key, val) in &pairs {
+            bytes.extend_from_slice(&serialize(key, Some(val), 0)?);
+        }
+        self.append_wal_bytes(&pairs[0].0, bytes)?;
+        self.inner.counters.inserts.fetch_add(pairs.len() as u64, Ordering::Relaxed);
+        for (key, val) in pairs {
+            self.family.apply(key, Entry::new(Some(val)))?;
+        }
+        Ok(())
+    }
+
+    // Refuses a value longer than max_value_bytes, or too long for its length prefix, whose top
+    // values are taken by the record markers.
+    fn check_value_size(&self, key: &K, val: &V) -> Result<(), DingoError> {
+        let len = bincode::serialized_size(val)?;
+        if len > self.inner.max_value_bytes as u64 || len >= RANGE_DELETE as u64 {
+            return Err(DingoError::ValueTooLarge { key: key.encode(), len });
+        }
+        Ok(())
+    }
+
+    // Nothing is written if any value of the entry is too large.
+    fn write(&self, key: K, entry: Entry<V>) -> Result<(), DingoError> {
+        for val in entry.val.iter().chain(&entry.operands) {
+            self.check_value_size(&key, val)?;
+        }
+        let _writing = self.inner.writing.lock()?;
+        self.write_locked(key, entry)
+    }
+
+    // write, for callers that already hold the writing lock and have checked the entry's size.
+    fn write_locked(&self, key: K, entry: Entry<V>) -> Result<(), DingoError> {
+        if self.family.size_with(&key, &entry)? > self.inner.memtable_size as u64 {
+            self.start_flush(true)?;
+        }
+        self.append_wal(&key, &entry)?;
+        self.family.apply(key, entry)
+    }
+
+    fn append_wal(&self, key: &K, entry: &Entry<V>) -> Result<(), DingoError> {
+        self.append_wal_bytes(key, serialize_entry(key, entry)?)
+    }
+
+    // Appends a record or batch starting with `key` for this handle's family, behind a marker
+    // naming the family unless it's the default one.
+    fn append_wal_bytes(&self, key: &K, mut bytes: Vec<u8>) -> Result<(), DingoError> {
+        if !self.family.name.is_empty() {
+            bytes.splice(0..0, family_marker(key, &self.family.name));
+        }
+        let mut wal = self.inner.wal.lock()?;
+        wal.write_all(&bytes)?;
+        if self.inner.durability.sync_writes() {
+            wal.sync_data()?;
+        }
+        Ok(())
+    }
+
+    // Loads the WAL back into the memtable. If the process died while a background flush was
+    // running, the older log it left behind is replayed first, then both are folded into a
+    // single WAL so nothing is lost when the memtable is next rotated out.
+    fn recover_wal(&self) -> Result<(), DingoError> {
+        let flushing_path = self.flushing_wal_path();
+        let mut records = Vec::new();
+        let interrupted_flush = Path::new(&flushing_path).exists();
+        if interrupted_flush {
+            let log = OpenOptions::new().read(true).write(true).open(&flushing_path)?;
+            records.extend(self.read_log(&log, flushing_path.clone())?);
+        }
+        {
+            let wal = self.inner.wal.lock()?;
+            records.extend(self.read_log(&wal, self.wal_path())?);
+        }
+        for logged in records {
+            match logged {
+                Logged::Entry(family, key, entry) => self.inner.families.get(&family)?.apply(key, entry)?,
+                Logged::RangeDelete(family, tombstone) => self.inner.families.get(&family)?.delete_range(tombstone)?,
+            }
+        }
+
+        if interrupted_flush {
+            let tmp_path = format!("{}.wal.tmp", self.inner.prefix());
+            let mut tmp = BufWriter::new(File::create(&tmp_path)?);
+            // Each memtable's range tombstones go first, as they're older than all of its entries.
+            for family in self.inner.families.all()? {
+                let objs = family.objs.read()?;
+                let mut records = Vec::new();
+                for tombstone in &objs.range_tombstones {
+                    records.push((&tombstone.start, encode_range_delete(tombstone)));
+                }
+                for (key, entry) in &objs.entries {
+                    records.push((key, serialize_entry(key, entry)?));
+                }
+                for (key, bytes) in records {
+                    if !family.name.is_empty() {
+                        tmp.write_all(&family_marker(key, &family.name))?;
+                    }
+                    tmp.write_all(&bytes)?;
+                }
+            }
+            let tmp = tmp.into_inner().map_err(|e| e.into_error())?;
+            if self.inner.durability.sync_files() {
+                tmp.sync_all()?;
+            }
+            std::fs::rename(&tmp_path, self.wal_path())?;
+            self.inner.durability.sync_dir_of(&self.wal_path())?;
+            *self.inner.wal.lock()? = OpenOptions::new().read(true).append(true).open(self.wal_path())?;
+            std::fs::remove_file(&flushing_path)?;
+            self.inner.durability.sync_dir_of(&flushing_path)?;
+        }
+        Ok(())
+    }
+
+    // Reads every complete record in a log, along with the family it belongs to. A crash can
+    // leave a partially written record at the tail; the log is truncated back to the last
+    // complete one.
+    fn read_log(&self, mut log: &File, filename: String) -> Result<Vec<Logged<K, V>>, DingoError> {
+        let mut records = Vec::new();
+        let mut valid_len = 0u64;
+        log.seek(SeekFrom::Start(0))?;
+        let mut reader = RecordReader {
+            inner: BufReader::new(log),
+            filename,
+            offset: 0,
+            end: log.metadata()?.len(),
+            block_size: 0,
+            block_end: 0,
+            versioned: false,
+            raw_values: false,
+            // Recovery needs to know where the log was torn to cut it off there.
+            torn_tail: false,
+        };
+        // A batch only counts once every one of its records made it to disk.
+        let mut pending = 0u64;
+        let mut batch = Vec::new();
+        // Set by a family marker until the record or batch after it is complete.
+        let mut family = None;
         loop {
-            match f.read_exact(&mut tempbuffer) {
-                Err(_) => break,
-                Ok(_) => {
-                    let keyparse = u64::from_be_bytes(tempbuffer);
-                    let mut value_len_buffer = [0u8; 4];
-                    f.read_exact(&mut value_len_buffer); 
-                    let valuelen = u32::from_be_bytes(value_len_buffer);
-                    let mut valbuff = vec![0u8; valuelen as usize];
-                    f.read_exact(&mut valbuff).unwrap();
-                    if keyparse == key {
-                        return Some(valbuff);
+            match reader.read_record() {
+                Ok(Some(RawRecord::Family(name))) if pending == 0 && family.is_none() => {
+                    family = Some(name);
+                    continue;
+                }
+                Ok(Some(RawRecord::BatchStart(count))) if pending == 0 => pending = count,
+                Ok(Some(RawRecord::BatchStart(_) | RawRecord::Family(_))) => {
+                    return Err(DingoError::Corruption { file: reader.filename, offset: reader.offset });
+                }
+                Ok(Some(RawRecord::RangeDelete(tombstone))) => {
+                    batch.push(Logged::RangeDelete(family.clone().unwrap_or_default(), tombstone));
+                    pending = pending.saturating_sub(1);
+                }
+                Ok(Some(record)) => {
+                    let (key, entry) = record.decode(&reader.filename)?;
+                    batch.push(Logged::Entry(family.clone().unwrap_or_default(), key, entry));
+                    pending = pending.saturating_sub(1);
+                }
+                Ok(None) => break,
+                Err(DingoError::Io(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            if pending == 0 {
+                valid_len = reader.offset;
+                records.append(&mut batch);
+                family = None;
+            }
+        }
+        if log.metadata()?.len() > valid_len {
+            log.set_len(valid_len)?;
+            if self.inner.durability.sync_files() {
+                log.sync_all()?;
+            }
+        }
+        Ok(records)
+    }
+
+    pub fn get(&self, key: K) -> Result<Option<V>, DingoError> {
+        self.inner.counters.gets.fetch_add(1, Ordering::Relaxed);
+        self.find(key, true)
+    }
+
+    // get without counting the lookup, and leaving what the SSTables give out of the cache unless
+    // `fill_cache` is set.
+    fn find(&self, key: K, fill_cache: bool) -> Result<Option<V>, DingoError> {
+        let operator = self.family.merge_operator;
+        // Taken before the memtables are checked, so a write to the key landing from then on
+        // keeps what the SSTables give below out of the cache.
+        let generation = self.family.cache.lock()?.generation();
+        // Check in-memory store first. Pending merges found on the way, newest first, are laid
+        // over whatever older entry turns up.
+        let mut merges = Vec::new();
+        let objs = self.family.objs.read()?;
+        let immutable = self.family.immutable.read()?;
+        for memtable in [Some(&**objs), immutable.as_deref()].into_iter().flatten() {
+            match memtable.lookup(&key) {
+                Some(entry) if entry.is_merge() => merges.push(entry),
+                Some(entry) => return Ok(stack(merges, entry, operator)?.into_live()),
+                None => {}
+            }
+        }
+        // Taken before the memtables are let go, so a flush can't hand the immutable memtable's
+        // merges to a table in between and have them counted twice.
+        let flushed_files = self.family.flushed_files.read()?;
+        drop(immutable);
+        drop(objs);
+        let cached = self.family.cache.lock()?.get(&key);
+        let entry = match cached {
+            Some(entry) => entry,
+            None => {
+                let entry = self.inner.tables.get(flushed_files.tables(), &key, operator)?;
+                // With merges pending in memory, the next flush adds to what the SSTables hold
+                // for the key, so it isn't cached.
+                if fill_cache && merges.is_empty() {
+                    self.family.cache.lock()?.insert_if_current(generation, key, entry.clone());
+                }
+                entry
+            }
+        };
+        Ok(stack(merges, entry, operator)?.into_live())
+    }
+
+    // Looks up every key in one go, returning the values in the same order as `keys`. Keys are
+    // resolved from memory first; the rest, and those with merges pending in memory, are looked
+    // up table by table, newest first, so each SSTable is opened at most once however many of the
+    // keys it holds.
+    pub fn get_many(&self, keys: &[K]) -> Result<Vec<Option<V>>, DingoError> {
+        self.inner.counters.gets.fetch_add(keys.len() as u64, Ordering::Relaxed);
+        let operator = self.family.merge_operator;
+        let mut results = vec![Entry::new(None); keys.len()];
+        // Per key, the pending merges found in memory, newest first, to lay over the result.
+        let mut merges = vec![Vec::new(); keys.len()];
+        let objs = self.family.objs.read()?;
+        let immutable = self.family.immutable.read()?;
+        // As in get, taken before the memtables are let go.
+        let flushed_files = self.family.flushed_files.read()?;
+        let mut cache = self.family.cache.lock()?;
+        // Writes can't land while the memtables are locked, so this is current as of the lookups
+        // in them below.
+        let generation = cache.generation();
+        // Indices into keys still to be found, in key order so each file is read front to back.
+        let mut pending = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            let mut in_memory = None;
+            for memtable in [Some(&**objs), immutable.as_deref()].into_iter().flatten() {
+                match memtable.lookup(key) {
+                    Some(entry) if entry.is_merge() => merges[i].push(entry),
+                    Some(entry) => {
+                        in_memory = Some(entry);
+                        break;
                     }
+                    None => {}
+                }
+            }
+            match in_memory.or_else(|| cache.get(key)) {
+                Some(entry) => results[i] = entry,
+                None => pending.push(i),
+            }
+        }
+        drop(cache);
+        drop(immutable);
+        drop(objs);
+        pending.sort_by(|a, b| keys[*a].cmp(&keys[*b]));
+        let looked_up = pending.clone();
+        // Likewise, the pending merges found so far in SSTables for the keys still to be found.
+        let mut table_merges = vec![Vec::new(); keys.len()];
 
+        for meta in flushed_files.tables().iter().rev() {
+            if pending.is_empty() {
+                break;
+            }
+            // Tables none of the keys fall within aren't opened at all.
+            if !pending.iter().any(|i| meta.spans(&keys[*i])) {
+                continue;
+            }
+            let filename = &meta.filename;
+            let table = self.inner.tables.table_offsets(filename)?;
+            let mut file = None;
+            let mut still_pending = Vec::with_capacity(pending.len());
+            for i in pending {
+                let key = &keys[i];
+                if !meta.spans(key) {
+                    still_pending.push(i);
+                    continue;
                 }
+                let found = match self.inner.tables.may_contain(filename, key)? {
+                    false => None,
+                    true => self.inner.tables.seek_in(&table, filename, &mut file, key)?,
+                };
+                match found.map(|entry| entry.decode(key, filename)).transpose()? {
+                    Some(entry) if entry.is_merge() => {
+                        table_merges[i].push(entry);
+                        still_pending.push(i);
+                    }
+                    Some(entry) => results[i] = stack(std::mem::take(&mut table_merges[i]), entry, operator)?,
+                    None if meta.deletes(key)? => {
+                        results[i] = stack(std::mem::take(&mut table_merges[i]), Entry::new(None), operator)?;
+                    }
+                    None => still_pending.push(i),
+                }
+            }
+            pending = still_pending;
+        }
+        drop(flushed_files);
+        for i in pending {
+            results[i] = stack(std::mem::take(&mut table_merges[i]), Entry::new(None), operator)?;
+        }
+
+        let mut cache = self.family.cache.lock()?;
+        // As in get, keys with merges pending in memory aren't cached.
+        for i in looked_up.into_iter().filter(|i| merges[*i].is_empty()) {
+            cache.insert_if_current(generation, keys[i].clone(), results[i].clone());
+        }
+        drop(cache);
+        results
+            .into_iter()
+            .zip(merges)
+            .map(|(entry, merges)| Ok(stack(merges, entry, operator)?.into_live()))
+            .collect()
+    }
+
+    // Like get, but never decodes the value. Bloom filters rule out most SSTables without
+    // touching disk.
+    pub fn contains_key(&self, key: K) -> Result<bool, DingoError> {
+        let objs = self.family.objs.read()?;
+        if let Some(entry) = objs.lookup(&key) {
+            return Ok(entry.present());
+        }
+        let immutable = self.family.immutable.read()?;
+        if let Some(entry) = immutable.as_ref().and_then(|memtable| memtable.lookup(&key)) {
+            return Ok(entry.present());
+        }
+        if let Some(entry) = self.family.cache.lock()?.get(&key) {
+            return Ok(entry.present());
+        }
+        let flushed_files = self.family.flushed_files.read()?;
+        for table in flushed_files.tables().iter().rev() {
+            if !table.spans(&key) {
+                continue;
+            }
+            let found = match self.inner.tables.may_contain(&table.filename, &key)? {
+                false => None,
+                true => self.inner.tables.seek_key(&table.filename, &key)?,
+            };
+            match found {
+                Some(entry) => return Ok(entry.present()),
+                None if table.deletes(&key)? => return Ok(false),
+                None => {}
+            }
+        }
+        Ok(false)
+    }
+
+    // Number of live keys. Only the memtable is counted while nothing has been flushed;
+    // otherwise every SSTable is scanned end to end so shadowed and deleted keys can be
+    // discounted, which costs as much as iterating the whole store.
+    pub fn len(&self) -> Result<usize, DingoError> {
+        {
+            let objs = self.family.objs.read()?;
+            let immutable = self.family.immutable.read()?;
+            if immutable.is_none() && self.family.flushed_files.read()?.is_empty() {
+                return Ok(objs.entries.values().filter(|entry| entry.present()).count());
             }
+        }
+        let mut len = 0;
+        for item in self.range(..)? {
+            item?;
+            len += 1;
+        }
+        Ok(len)
+    }
 
-        } 
+    // Stops at the first live key, so it's cheap even when len() isn't.
+    pub fn is_empty(&self) -> Result<bool, DingoError> {
+        match self.range(..)?.next() {
+            Some(item) => item.map(|_| false),
+            None => Ok(true),
+        }
+    }
 
-        return None;
+    // Counts are cumulative since the store was opened, across all column families; the memtable
+    // and SSTables are the handle's own family's, plus the shared WAL. A flush still running in
+    // the background isn't counted until it finishes, though its memtable no longer counts
+    // towards memtable_bytes either.
+    pub fn stats(&self) -> Result<DingoStats, DingoError> {
+        let mut stats = self.inner.counters.snapshot();
+        stats.memtable_bytes = self.family.treesize.load(Ordering::SeqCst);
+        let flushed_files = self.family.flushed_files.read()?;
+        stats.sstables = flushed_files.len();
+        stats.disk_bytes = self.inner.wal.lock()?.metadata()?.len();
+        for table in flushed_files.tables() {
+            stats.disk_bytes += std::fs::metadata(&table.filename)?.len();
+        }
+        Ok(stats)
     }
-    pub fn get(&self, key: u64) -> Option<String> {
-        // Check in-memory store first
-        let objs = self.objs.lock().unwrap();
-        if let Some(val) = objs.get(&key) {
-            return Some(val.clone());
+
+    // The first and last key of each of the handle's family's SSTables, by level. Level 0 holds
+    // the tables as they were flushed; the deeper ones only exist under CompactionStyle::Leveled.
+    pub fn levels(&self) -> Result<BTreeMap<usize, Vec<(K, K)>>, DingoError> {
+        let mut levels: BTreeMap<usize, Vec<(K, K)>> = BTreeMap::new();
+        for table in self.family.flushed_files.read()?.tables() {
+            levels.entry(table.level).or_default().push((table.firstkey.clone(), table.lastkey.clone()));
         }
-        let flushed_files = self.flushed_files.lock().unwrap();
-        let mut idx = 0;
-        let keys = flushed_files.keys().collect::<Vec<&u64>>();
-        while idx < keys.len() && keys[idx] <= &key {
-            idx+=1;
+        Ok(levels)
+    }
+
+    // Captures the store as it is now. Nothing is copied: the snapshot shares the memtables until
+    // the next write replaces them, and holds on to the SSTables so compaction leaves them on
+    // disk until it's dropped.
+    pub fn snapshot(&self) -> Result<Snapshot<K, V>, DingoError> {
+        let objs = self.family.objs.read()?;
+        let immutable = self.family.immutable.read()?;
+        let flushed_files = self.family.flushed_files.read()?;
+        for table in flushed_files.tables() {
+            self.inner.tables.acquire(&table.filename)?;
         }
-        let target_filename = flushed_files.get(&(keys[idx-1])).unwrap();
-        let find_res = self.seek_key(target_filename, key);
-        match find_res {
-            Some(v) => {
+        Ok(Snapshot::new(
+            Arc::clone(&objs),
+            immutable.clone(),
+            flushed_files.tables().to_vec(),
+            Arc::clone(&self.inner.tables),
+            self.family.merge_operator,
+        ))
+    }
 
-                let value = String::from_utf8_lossy(&v);
-                return Some(value.to_string());
-            },
-            None => {
-                return None;
+    // Yields the live key/value pairs in `range` in ascending key order, merging the memtable
+    // with every SSTable that could overlap it. Newer writes shadow older ones and deleted keys
+    // are skipped. SSTables are streamed as the iterator advances; only the memtable's slice of
+    // the range is copied up front.
+    pub fn range(
+        &self,
+        range: impl RangeBounds<K>,
+    ) -> Result<impl Iterator<Item = Result<(K, V), DingoError>> + '_, DingoError> {
+        self.scan(range, false)
+    }
+
+    // Like range, but in descending key order. SSTables are read backwards a chunk of records at
+    // a time, a chunk running from one entry of the file's sparse index to the next.
+    pub fn range_rev(
+        &self,
+        range: impl RangeBounds<K>,
+    ) -> Result<impl Iterator<Item = Result<(K, V), DingoError>> + '_, DingoError> {
+        self.scan(range, true)
+    }
+
+    fn scan(
+        &self,
+        range: impl RangeBounds<K>,
+        descending: bool,
+    ) -> Result<impl Iterator<Item = Result<(K, V), DingoError>> + '_, DingoError> {
+        let start = range.start_bound().cloned();
+        let end = range.end_bound().cloned();
+        let from = match &start {
+            Bound::Included(s) | Bound::Excluded(s) => Some(s),
+            Bound::Unbounded => None,
+        };
+        let to = match &end {
+            Bound::Included(e) | Bound::Excluded(e) => Some(e),
+            Bound::Unbounded => None,
+        };
+        // A range that ends before it starts holds no keys, and BTreeMap::range would panic on
+        // it, so nothing is read at all.
+        let empty = tombstone::bounded((start.clone(), end.clone()), None, None).is_some_and(|range| range.is_empty());
+
+        let objs = self.family.objs.read()?;
+        let immutable = self.family.immutable.read()?;
+        let flushed_files = self.family.flushed_files.read()?;
+        let (tables, memtables) = match empty {
+            true => (&[][..], [None, None]),
+            false => (flushed_files.tables(), [immutable.as_deref(), Some(&**objs)]),
+        };
+        let mut sources = Vec::with_capacity(tables.len() + 2);
+        let mut range_tombstones = Vec::with_capacity(tables.len() + 2);
+        for table in tables {
+            let starts_after_end = match &end {
+                Bound::Included(e) => table.firstkey > *e,
+                Bound::Excluded(e) => table.firstkey >= *e,
+                Bound::Unbounded => false,
+            };
+            let ends_before_start = match &start {
+                Bound::Included(s) => table.lastkey < *s,
+                Bound::Excluded(s) => table.lastkey <= *s,
+                Bound::Unbounded => false,
+            };
+            if starts_after_end || ends_before_start {
+                continue;
+            }
+            sources.push(match descending {
+                false => Source::Table(table::open_table(&table.filename, from)?),
+                true => Source::TableRev(table::open_table_rev(&table.filename, to)?),
+            });
+            range_tombstones.push(table.range_tombstones()?.to_vec());
+        }
+        for memtable in memtables.into_iter().flatten() {
+            let mut mem: Vec<_> = memtable.entries.range((start.clone(), end.clone())).map(|(k, v)| (k.clone(), v.clone())).collect();
+            if descending {
+                mem.reverse();
             }
+            sources.push(Source::Mem(mem.into_iter()));
+            range_tombstones.push(memtable.range_tombstones.clone());
+        }
+        drop(flushed_files);
+        drop(immutable);
+        drop(objs);
+
+        let merged = MergeIter::new(sources, range_tombstones, start, end, descending, self.family.merge_operator, true)?;
+        Ok(merged.filter_map(|item| match item {
+            Ok((key, entry)) => entry.into_live().map(|val| Ok((key, val))),
+            Err(e) => Some(Err(e)),
+        }))
+    }
+
+    // Every live key in ascending order, streamed the same way as range(..).
+    pub fn keys(&self) -> Result<impl Iterator<Item = Result<K, DingoError>> + '_, DingoError> {
+        Ok(self.range(..)?.map(|item| item.map(|(key, _)| key)))
+    }
+
+}
+
+impl<K: Key, V: Value> Inner<K, V> {
+    // Every file the store creates starts with this: fname inside data_dir.
+    fn prefix(&self) -> String {
+        self.data_dir.join(&self.fname).to_string_lossy().to_string()
+    }
+
+    // Prefix of a family's SSTables: the store's prefix for the default family, otherwise the
+    // prefix followed by the family's name.
+    fn table_prefix(&self, family: &Family<K, V>) -> String {
+        match family.name.as_str() {
+            "" => self.prefix(),
+            name => format!("{}_{}", self.prefix(), name),
         }
     }
 
+    // Table order on disk comes from the timestamp in the name, so every new name must sort after
+    // all earlier ones, even a name freed by compaction within the same millisecond. Timestamps
+    // are only millisecond resolution, so bump past the last one handed out, and past any file
+    // already on disk rather than clobbering it.
+    fn data_fname(&self, family: &Family<K, V>) -> String {
+        let prefix = self.table_prefix(family);
+        let now = now_millis();
+        let last = self.last_table_ts.load(Ordering::SeqCst);
+        let mut ts = now.max(last + 1);
+        loop {
+            let data_fname = format!("{}_{}.data", prefix, ts);
+            if !Path::new(&data_fname).exists() {
+                self.last_table_ts.store(ts, Ordering::SeqCst);
+                return data_fname;
+            }
+            ts += 1;
+        }
+    }
 
-    fn flush(&mut self) -> String {
-        let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
-        let data_fname = format!("{}_{}.data", self.fname, ts);
-        let mut objs = self.objs.lock().unwrap();
-        let mut data_file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&data_fname)
-            .unwrap();
-        let mut firstkey : Option<u64> = None;
-        for (key, val) in objs.iter(){
-            if firstkey.is_none() {
-                firstkey = Some(*key);
+    // Compacts every family, each on its own. Runs on the flush thread after a flush that a write
+    // started, so the write doesn't wait for it.
+    fn compact(&self) -> Result<(), DingoError> {
+        for family in self.families.all()? {
+            match self.compaction_style {
+                CompactionStyle::SizeTiered => self.compact_all(&family, false)?,
+                CompactionStyle::Leveled => self.compact_levels(&family)?,
+                CompactionStyle::SmallRuns => self.compact_small_runs(&family)?,
             }
-            let bytes = self.serialize(*key, val);
-            
-            // Write to data file
-            data_file.write_all(&bytes).unwrap();
-            
-            // Update index
         }
-        
-        data_file.sync_all().unwrap();
-        
-        let mut flushed_files = self.flushed_files.lock().unwrap();
-        flushed_files.insert(firstkey.unwrap(), data_fname.clone());
-        objs.clear();
-        self.treesize = 0;
-        data_fname
+        Ok(())
+    }
 
+    // DingoStore::compact_now, which the compaction_interval thread calls without a handle.
+    fn compact_now(&self) -> Result<(), DingoError> {
+        let _writing = self.writing.lock()?;
+        // The merged table is named after the one still being flushed, so wait for that to land
+        // first or the two would swap order when the tables are next loaded.
+        self.finish_flush()?;
+        for family in self.families.all()? {
+            self.compact_all(&family, true)?;
+        }
+        Ok(())
+    }
+
+    // Waits for the background flush, if one is running, and surfaces its error.
+    fn finish_flush(&self) -> Result<(), DingoError> {
+        let flushing = self.flushing.lock()?.take();
+        match flushing {
+            Some(handle) => handle
+                .join()
+                .map_err(|_| std::io::Error::other("background flush panicked"))?,
+            None => Ok(()),
+        }
+    }
+
+    // Merges every SSTable of a family into one once there are more than compaction_trigger of
+    // them, or as long as there are any if `force` is set. Each file is streamed record by record
+    // through a k-way merge, so only the head record of each file is held in memory at a time.
+    // When a key appears in several files the newest file wins. Every SSTable takes part in the
+    // merge, so no older file can still hold a value the tombstones need to shadow and they're
+    // dropped, and every pending merge is applied. The merged table lands in the deepest level,
+    // which for size-tiered compaction is level 0.
+    //
+    // Compactions only run on the flush thread once its flush is done, or under the writing lock
+    // once there's no flush running, so nothing else changes the family's tables in the
+    // meantime. The merge works from a copy of the list, and reads go on against the old tables
+    // until the merged one takes their place.
+    fn compact_all(&self, family: &Family<K, V>, force: bool) -> Result<(), DingoError> {
+        let tables = family.flushed_files.read()?.len();
+        if tables == 0 || (!force && tables <= self.compaction_trigger) {
+            return Ok(());
+        }
+        let old_files = family.flushed_files.read()?.tables().to_vec();
+        let merged = self.merge_tables(family, &old_files, old_files[0].level, true)?;
+
+        family.cache.lock()?.clear();
+        // The old files are only removed once the manifest no longer names them.
+        family.flushed_files.write()?.replace(&old_files, merged.into_iter().collect());
+        self.families.write_manifest()?;
+        for table in old_files {
+            self.tables.retire(&table.filename)?;
+        }
+        self.counters.compactions.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Once a family has more than compaction_trigger SSTables, merges each run of adjacent level 0
+    // tables adding up to less than twice memtable_size bytes into one table, which takes the
+    // run's place in lookup order (see CompactionStyle::SmallRuns). Tables outside the runs are
+    // left as they are, so tombstones and pending merges are only resolved in a run that starts
+    // at the family's oldest table.
+    fn compact_small_runs(&self, family: &Family<K, V>) -> Result<(), DingoError> {
+        let target = 2 * self.memtable_size as u64;
+        // As in compact_all, the runs are merged without holding the family's tables.
+        let (runs, oldest) = {
+            let flushed_files = family.flushed_files.read()?;
+            let runs = flushed_files.small_runs(target);
+            if flushed_files.len() <= self.compaction_trigger || runs.is_empty() {
+                return Ok(());
+            }
+            (runs, flushed_files.tables()[0].filename.clone())
+        };
+        let mut merged = Vec::with_capacity(runs.len());
+        for run in &runs {
+            merged.push(self.merge_tables(family, run, 0, run[0].filename == oldest)?);
+        }
+
+        family.cache.lock()?.clear();
+        let mut flushed_files = family.flushed_files.write()?;
+        for (run, table) in runs.iter().zip(merged) {
+            flushed_files.replace_run(run, table);
+        }
+        drop(flushed_files);
+        self.families.write_manifest()?;
+        for table in runs.iter().flatten() {
+            self.tables.retire(&table.filename)?;
+        }
+        self.counters.compactions.fetch_add(runs.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Merges `inputs`, in lookup order, into a single table in `level`. With `bottom` set nothing
+    // older than the inputs is left, so tombstones, expired values and range tombstones are
+    // dropped and every pending merge is applied; otherwise they're all kept to shadow or apply
+    // to older tables.
+    fn merge_tables(&self, family: &Family<K, V>, inputs: &[TableMeta<K>], level: usize, bottom: bool) -> Result<Option<TableMeta<K>>, DingoError> {
+        let mut sources: Vec<Source<K, V>> = Vec::with_capacity(inputs.len());
+        for table in inputs {
+            sources.push(Source::Table(table::open_table::<K>(&table.filename, None)?));
+        }
+        let input_tombstones = inputs.iter().map(|table| table.range_tombstones().map(<[_]>::to_vec)).collect::<Result<Vec<_>, _>>()?;
+        let range_tombstones = match bottom {
+            true => Vec::new(),
+            false => input_tombstones.iter().flatten().cloned().collect(),
+        };
+
+        let (mut writer, data_fname) = self.create_table(family, &range_tombstones)?;
+        let merge = MergeIter::new(sources, input_tombstones, Bound::Unbounded, Bound::Unbounded, false, family.merge_operator, bottom)?;
+        for item in merge {
+            let (key, entry) = item?;
+            if bottom && entry.live().is_none() {
+                continue;
+            }
+            writer.add(&key, &entry)?;
+        }
+        self.finish_table(writer, data_fname, level, range_tombstones)
+    }
+
+    // Runs leveled compactions on a family until none is due (see CompactionStyle::Leveled).
+    // Each one merges its input tables like compact_all, but splits the output into tables of
+    // about memtable_size bytes so the level it lands in stays a run of small, non-overlapping
+    // tables. Tombstones are only dropped when nothing deeper could hold a value they shadow;
+    // likewise, pending merges whose value isn't among the inputs stay pending unless nothing
+    // deeper could hold it. Range tombstones that have to be kept are carried over to the output,
+    // which is then written as a single table so the ranges can't straddle two tables.
+    fn compact_levels(&self, family: &Family<K, V>) -> Result<(), DingoError> {
+        // Deeper levels only grow through compaction out of level 0.
+        if family.flushed_files.read()?.level(0).len() <= self.compaction_trigger {
+            return Ok(());
+        }
+        let table_bytes = self.memtable_size as u64;
+        loop {
+            // As in compact_all, the inputs are merged without holding the family's tables.
+            let Some(compaction) = family.flushed_files.write()?.pick_compaction(self.compaction_trigger, table_bytes) else {
+                break;
+            };
+            let mut sources: Vec<Source<K, V>> = Vec::with_capacity(compaction.inputs.len());
+            for table in &compaction.inputs {
+                sources.push(Source::Table(table::open_table::<K>(&table.filename, None)?));
+            }
+            let input_tombstones =
+                compaction.inputs.iter().map(|table| table.range_tombstones().map(<[_]>::to_vec)).collect::<Result<Vec<_>, _>>()?;
+            let mut range_tombstones: Vec<_> = match compaction.bottom {
+                true => Vec::new(),
+                false => input_tombstones.iter().flatten().cloned().collect(),
+            };
+
+            let mut merged = Vec::new();
+            let mut writer = None;
+            let merge = MergeIter::new(
+                sources,
+                input_tombstones,
+                Bound::Unbounded,
+                Bound::Unbounded,
+                false,
+                family.merge_operator,
+                compaction.bottom,
+            )?;
+            for item in merge {
+                let (key, entry) = item?;
+                if compaction.bottom && entry.live().is_none() {
+                    continue;
+                }
+                if writer.is_none() {
+                    writer = Some(self.create_table(family, &range_tombstones)?);
+                }
+                let (table_writer, _) = writer.as_mut().unwrap();
+                table_writer.add(&key, &entry)?;
+                if table_writer.len() >= table_bytes && range_tombstones.is_empty() {
+                    let (table_writer, data_fname) = writer.take().unwrap();
+                    merged.extend(self.finish_table(table_writer, data_fname, compaction.level, Vec::new())?);
+                }
+            }
+            if writer.is_none() && !range_tombstones.is_empty() {
+                writer = Some(self.create_table(family, &range_tombstones)?);
+            }
+            if let Some((table_writer, data_fname)) = writer {
+                let range_tombstones = std::mem::take(&mut range_tombstones);
+                merged.extend(self.finish_table(table_writer, data_fname, compaction.level, range_tombstones)?);
+            }
+
+            family.flushed_files.write()?.replace(&compaction.inputs, merged);
+            self.families.write_manifest()?;
+            for table in compaction.inputs {
+                self.tables.retire(&table.filename)?;
+            }
+            self.counters.compactions.fetch_add(1, Ordering::Relaxed);
+        }
+        family.cache.lock()?.clear();
+        Ok(())
+    }
+
+    // Starts a table for compaction to write `family`'s records to, opening with the range
+    // tombstones it's to carry. Returns the table's name along with its writer.
+    fn create_table(&self, family: &Family<K, V>, range_tombstones: &[RangeTombstone<K>]) -> Result<(TableWriter<K>, String), DingoError> {
+        let data_fname = self.data_fname(family);
+        let mut writer = TableWriter::create(&data_fname, self.compression, self.block_size, self.durability)?;
+        for tombstone in range_tombstones {
+            writer.add_range_tombstone(tombstone)?;
+        }
+        Ok((writer, data_fname))
+    }
+
+    // Finishes a table written by compaction as part of `level`, along with the range tombstones
+    // it carries. A table that came out with neither records nor tombstones is deleted and None
+    // returned.
+    fn finish_table(
+        &self,
+        writer: TableWriter<K>,
+        data_fname: String,
+        level: usize,
+        range_tombstones: Vec<RangeTombstone<K>>,
+    ) -> Result<Option<TableMeta<K>>, DingoError> {
+        let (range, bloom) = writer.finish()?;
+        let Some((firstkey, lastkey)) = tombstone::span(range, &range_tombstones) else {
+            std::fs::remove_file(&data_fname)?;
+            return Ok(None);
+        };
+        self.tables.add(&data_fname, bloom)?;
+        let size = std::fs::metadata(&data_fname)?.len();
+        let range_tombstones = Arc::new(OnceLock::from(range_tombstones));
+        Ok(Some(TableMeta { level, firstkey, lastkey, filename: data_fname, size, range_tombstones }))
+    }
+}
+
+impl<K: Key, V: Value> DingoStore<K, V> {
+    // Merges every family's SSTables into a single table, dropping the tombstones and expired
+    // values they hold, however few tables there are. The memtables are left as they are.
+    pub fn compact_now(&mut self) -> Result<(), DingoError> {
+        self.inner.compact_now()
+    }
+
+    // Swaps every family's memtable out for a fresh one and writes the non-empty ones to new
+    // SSTables on a background thread. The families share the WAL, so they're flushed together:
+    // the log is rotated along with the memtables, and the old log is only deleted once all of
+    // the new SSTables are in the manifest. With `compact` set, the thread then runs whatever
+    // compaction the new tables make due. Returns the name of the SSTable this handle's family is
+    // being written to, None if its memtable was empty.
+    fn start_flush(&self, compact: bool) -> Result<Option<String>, DingoError> {
+        // Only one flush runs at a time, so the old log is never overwritten.
+        self.inner.finish_flush()?;
+        let mut flushing = Vec::new();
+        for family in self.inner.families.all()? {
+            if family.objs.read()?.is_empty() {
+                continue;
+            }
+            let data_fname = self.inner.data_fname(&family);
+            let writer = TableWriter::create(&data_fname, self.inner.compression, self.inner.block_size, self.inner.durability)?;
+            let memtable = {
+                let mut objs = family.objs.write()?;
+                let mut immutable = family.immutable.write()?;
+                let memtable = std::mem::take(&mut *objs);
+                *immutable = Some(Arc::clone(&memtable));
+                memtable
+            };
+            family.treesize.store(0, Ordering::SeqCst);
+            flushing.push((family, writer, memtable, data_fname));
+        }
+        if flushing.is_empty() {
+            return Ok(None);
+        }
+        let flushed_fname = flushing
+            .iter()
+            .find(|(family, ..)| Arc::ptr_eq(family, &self.family))
+            .map(|(.., data_fname)| data_fname.clone());
+
+        let flushing_path = self.flushing_wal_path();
+        {
+            let mut wal = self.inner.wal.lock()?;
+            std::fs::rename(self.wal_path(), &flushing_path)?;
+            *wal = OpenOptions::new().read(true).append(true).create(true).open(self.wal_path())?;
+            self.inner.durability.sync_dir_of(&flushing_path)?;
+        }
+
+        let inner = Arc::clone(&self.inner);
+        *self.inner.flushing.lock()? = Some(std::thread::spawn(move || {
+            for (family, writer, memtable, table) in flushing {
+                let (range, bloom) = write_memtable(writer, &memtable)?;
+                inner.tables.add(&table, bloom)?;
+                // A memtable holding nothing but range tombstones still needs a table to carry
+                // them.
+                let meta = match tombstone::span(range, &memtable.range_tombstones) {
+                    Some((firstkey, lastkey)) => {
+                        let size = std::fs::metadata(&table)?.len();
+                        let range_tombstones = Arc::new(OnceLock::from(memtable.range_tombstones.clone()));
+                        Some(TableMeta { level: 0, firstkey, lastkey, filename: table, size, range_tombstones })
+                    }
+                    None => None,
+                };
+                // Reads hold the immutable memtable until they have flushed_files, and the
+                // SSTable takes the memtable's place under both locks, so each key is read from
+                // exactly one of them: from neither would lose it, from both would count its
+                // pending merges twice. A crash before the manifest lists the table replays the
+                // rotated log.
+                let mut immutable = family.immutable.write()?;
+                if let Some(meta) = meta {
+                    family.flushed_files.write()?.push(meta);
+                }
+                *immutable = None;
+                drop(immutable);
+                inner.counters.flushes.fetch_add(1, Ordering::Relaxed);
+            }
+            inner.families.write_manifest()?;
+            std::fs::remove_file(flushing_path)?;
+            if compact {
+                inner.compact()?;
+            }
+            Ok(())
+        }));
+        Ok(flushed_fname)
+    }
+
+    // Writes every non-empty memtable out to an SSTable now, rather than once it fills up, and
+    // waits until the SSTables are on disk and listed in the manifest. The families share the
+    // WAL, so all of them are flushed, not just this handle's. Returns the file name of the
+    // SSTable written for this handle's family, or None if its memtable was empty.
+    pub fn flush(&mut self) -> Result<Option<String>, DingoError> {
+        let _writing = self.inner.writing.lock()?;
+        let flushed_fname = self.start_flush(false)?;
+        self.inner.finish_flush()?;
+        Ok(flushed_fname)
+    }
+
+    // Shuts the store down through this handle: flushes every memtable, waits for any flush or
+    // compaction still running, and fsyncs the SSTables, the manifest, the WAL and the directory
+    // they're in whatever the durability setting, so everything written is on disk when it
+    // returns. Unlike drop, it reports what goes wrong. The store's files and memory maps are
+    // released once its last handle is gone; clones and column family handles keep it open until
+    // they're dropped too.
+    pub fn close(self) -> Result<(), DingoError> {
+        // The timer's compactions run under the writing lock, so holding it waits for any that's
+        // under way, and the ones after a flush run on the flush thread finish_flush waits for.
+        let _writing = self.inner.writing.lock()?;
+        self.start_flush(false)?;
+        self.inner.finish_flush()?;
+        for family in self.inner.families.all()? {
+            for table in family.flushed_files.read()?.tables() {
+                File::open(&table.filename)?.sync_all()?;
+            }
+        }
+        match File::open(self.manifest_path()) {
+            Ok(manifest) => manifest.sync_all()?,
+            // Nothing has been flushed yet.
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.inner.wal.lock()?.sync_all()?;
+        durability::sync_dir_of(&self.wal_path())?;
+        Ok(())
+    }
+
+}
+
+impl<V: Value> DingoStore<u64, V> {
+    // For stores that pack a namespace into the high bits of their keys (say, tenant_id << 40 |
+    // inner_id): yields the live pairs whose key masked by `mask` equals `high_bits`, in
+    // ascending key order. It's a range scan from the lowest such key to the highest, so for a
+    // mask of contiguous high bits nothing outside the prefix is read; other masks still work,
+    // with the keys in between skipped.
+    pub fn scan_prefix(
+        &self,
+        high_bits: u64,
+        mask: u64,
+    ) -> Result<impl Iterator<Item = Result<(u64, V), DingoError>> + '_, DingoError> {
+        let prefix = high_bits & mask;
+        Ok(self.range(prefix..=prefix | !mask)?.filter(move |item| match item {
+            Ok((key, _)) => key & mask == high_bits,
+            Err(_) => true,
+        }))
+    }
+}
+
+// Async wrappers for use inside a tokio runtime. The store's file I/O is blocking, so each call
+// runs on tokio's blocking pool through a clone of the handle.
+impl<K: Key, V: Value> DingoStore<K, V> {
+    pub async fn get_async(&self, key: K) -> Result<Option<V>, DingoError> {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || store.get(key))
+            .await
+            .map_err(std::io::Error::other)?
+    }
+
+    pub async fn insert_async(&self, key: K, val: V) -> Result<(), DingoError> {
+        let mut store = self.clone();
+        tokio::task::spawn_blocking(move || store.insert(key, val))
+            .await
+            .map_err(std::io::Error::other)?
+    }
+}
+
+// Handles are meant to be sent to and shared between threads, so keep them Send + Sync.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<DingoStore>();
+};
+
+// Clones are handles to one store rather than copies of it: every handle reads and writes the
+// same memtable, WAL and SSTables, so a write through any of them is seen by all of them. A copy
+// that went its own way would still be writing to the same files on disk.
+impl<K: Key, V: Value> Clone for DingoStore<K, V> {
+    fn clone(&self) -> Self {
+        self.inner.handles.fetch_add(1, Ordering::SeqCst);
+        DingoStore { inner: Arc::clone(&self.inner), family: Arc::clone(&self.family) }
+    }
+}
+
+impl<K: Key, V: Value> Drop for DingoStore<K, V> {
+    // Once the last handle goes, writes out whatever is still in the memtable so the store can be
+    // reopened from its SSTables alone. Drop can't return an error, so a failed flush is only
+    // counted in the store's stats; the WAL still holds the writes in that case.
+    fn drop(&mut self) {
+        if self.inner.handles.fetch_sub(1, Ordering::SeqCst) > 1 {
+            return;
+        }
+        // The timer stops once it sees no handle left; waking it saves waiting out its interval.
+        if let Some(timer) = self.inner.timer.lock().ok().and_then(|mut timer| timer.take()) {
+            timer.thread().unpark();
+            let _ = timer.join();
+        }
+        if let Err(e) = self.flush() {
+            self.inner.counters.record_error(&e);
+        }
     }
-    
 }
@@ -0,0 +1,11 @@
+// Folds a merge operand into the value it's merged with, None when the key has no value: the
+// result is the key's new value. See DingoStore::merge.
+pub type MergeOperator<V> = fn(Option<&V>, &V) -> V;
+
+// A merge operator for counters kept as decimal strings: adds the operand to the value,
+// saturating at the bounds of an i64. A missing value, or anything that isn't an integer, counts
+// as 0.
+pub fn add_integers(existing: Option<&String>, operand: &String) -> String {
+    let parse = |s: &String| s.trim().parse::<i64>().unwrap_or(0);
+    existing.map_or(0, parse).saturating_add(parse(operand)).to_string()
+}
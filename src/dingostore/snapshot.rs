@@ -0,0 +1,55 @@
+use std::sync::Arc;
+
+use super::table::Tables;
+use super::{stack, DingoError, Frozen, Key, Memtable, MergeOperator, TableMeta, Value};
+
+// A read-only view of the store as it was when DingoStore::snapshot was called. Later writes,
+// flushes and compactions don't show through: the memtables it saw are shared rather than
+// copied, and the SSTables it saw are kept on disk until it's dropped.
+pub struct Snapshot<K: Key, V: Value> {
+    memtable: Arc<Memtable<K, V>>,
+    immutable: Frozen<K, V>,
+    // Every SSTable at the time, in lookup order.
+    files: Vec<TableMeta<K>>,
+    tables: Arc<Tables<K>>,
+    merge_operator: Option<MergeOperator<V>>,
+}
+
+impl<K: Key, V: Value> Snapshot<K, V> {
+    // Expects every file in `files` to have been acquired from `tables` already.
+    pub(super) fn new(
+        memtable: Arc<Memtable<K, V>>,
+        immutable: Frozen<K, V>,
+        files: Vec<TableMeta<K>>,
+        tables: Arc<Tables<K>>,
+        merge_operator: Option<MergeOperator<V>>,
+    ) -> Snapshot<K, V> {
+        Snapshot { memtable, immutable, files, tables, merge_operator }
+    }
+
+    pub fn get(&self, key: K) -> Result<Option<V>, DingoError> {
+        let mut merges = Vec::new();
+        for memtable in [Some(&*self.memtable), self.immutable.as_deref()].into_iter().flatten() {
+            match memtable.lookup(&key) {
+                Some(entry) if entry.is_merge() => merges.push(entry),
+                Some(entry) => return Ok(stack(merges, entry, self.merge_operator)?.into_live()),
+                None => {}
+            }
+        }
+        let entry = self.tables.get(&self.files, &key, self.merge_operator)?;
+        Ok(stack(merges, entry, self.merge_operator)?.into_live())
+    }
+}
+
+impl<K: Key, V: Value> Drop for Snapshot<K, V> {
+    // Lets go of the SSTables, deleting any that compaction has replaced since. A file that
+    // can't be deleted is only counted in stats(); it's no longer in the manifest, so nothing
+    // reads it.
+    fn drop(&mut self) {
+        for table in &self.files {
+            if let Err(e) = self.tables.release(&table.filename) {
+                self.tables.counters.record_error(&e);
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use super::DingoError;
+
+// A snapshot of the store's state and of what it has done since it was opened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DingoStats {
+    // Serialized size of the memtable, as compared against memtable_size_bytes.
+    pub memtable_bytes: u32,
+    pub sstables: usize,
+    // SSTables plus the WAL.
+    pub disk_bytes: u64,
+    pub inserts: u64,
+    pub deletes: u64,
+    pub merges: u64,
+    pub gets: u64,
+    // Memtables written out to an SSTable.
+    pub flushes: u64,
+    pub compactions: u64,
+    // Per-SSTable filter checks during lookups: a hit means the key may be in the file, a miss
+    // means the file was skipped without reading it.
+    pub bloom_hits: u64,
+    pub bloom_misses: u64,
+    // SSTables memory-mapped for lookups, see DingoStoreBuilder::mmap.
+    pub mmaps: u64,
+    // SSTable footers read to load a file's filter or offsets for lookups, see
+    // DingoStoreBuilder::preload_indexes.
+    pub footer_reads: u64,
+    // Failures in work nobody was waiting on: the flush when the last handle is dropped,
+    // compaction_interval's compactions and deleting the tables a Snapshot was holding.
+    pub background_errors: u64,
+    pub last_background_error: Option<String>,
+}
+
+// The cumulative counts behind DingoStats, bumped in place by the operations they count.
+#[derive(Default)]
+pub struct Counters {
+    pub inserts: AtomicU64,
+    pub deletes: AtomicU64,
+    pub merges: AtomicU64,
+    pub gets: AtomicU64,
+    pub flushes: AtomicU64,
+    pub compactions: AtomicU64,
+    pub bloom_hits: AtomicU64,
+    pub bloom_misses: AtomicU64,
+    pub mmaps: AtomicU64,
+    pub footer_reads: AtomicU64,
+    pub background_errors: AtomicU64,
+    pub last_background_error: Mutex<Option<String>>,
+}
+
+impl Counters {
+    // Fills in the counters; the rest of the snapshot comes from the store.
+    pub fn snapshot(&self) -> DingoStats {
+        DingoStats {
+            inserts: self.inserts.load(Ordering::Relaxed),
+            deletes: self.deletes.load(Ordering::Relaxed),
+            merges: self.merges.load(Ordering::Relaxed),
+            gets: self.gets.load(Ordering::Relaxed),
+            flushes: self.flushes.load(Ordering::Relaxed),
+            compactions: self.compactions.load(Ordering::Relaxed),
+            bloom_hits: self.bloom_hits.load(Ordering::Relaxed),
+            bloom_misses: self.bloom_misses.load(Ordering::Relaxed),
+            mmaps: self.mmaps.load(Ordering::Relaxed),
+            footer_reads: self.footer_reads.load(Ordering::Relaxed),
+            background_errors: self.background_errors.load(Ordering::Relaxed),
+            last_background_error: self.last_background_error.lock().map_or(None, |last| last.clone()),
+            ..DingoStats::default()
+        }
+    }
+
+    // Keeps a failure that has no caller to return it to around for stats().
+    pub fn record_error(&self, e: &DingoError) {
+        self.background_errors.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut last) = self.last_background_error.lock() {
+            *last = Some(e.to_string());
+        }
+    }
+}
@@ -0,0 +1,1020 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use memmap2::Mmap;
+use serde::Serialize;
+
+use super::{compression, decode_value, operands_payload, stack, Bloom, Compression, Counters, DingoError, Durability, Entry, Key, MergeOperator, TableMeta, Value, BATCH, EXPIRING, FAMILY, MERGE, RANGE_DELETE, TOMBSTONE};
+use super::tombstone::RangeTombstone;
+
+// Every INDEX_INTERVAL-th record of an SSTable gets an entry in its sparse index.
+const INDEX_INTERVAL: usize = 64;
+// SSTable footer: block size (u32, 0 for records written back to back), index offset (u64), index
+// entry count (u32), filter length in bytes (u32), filter hash count (u32), magic (u64). The index
+// of (encoded key, offset (u64)) entries and the Bloom filter sit between the records and the
+// footer. Files with this footer start with a header, HEADER_MAGIC (u32) then the format version
+// (u8), and every record opens with a type tag and is followed by a CRC32 of its bytes; every value
+// payload starts with a codec id (see Compression). Records start right after the header, or in
+// tables with blocks on the first block boundary, the header being padded out to a block of its
+// own. Each block starts on a multiple of the block size with the length of the records it holds
+// (u32) and is zero-padded up to the next boundary, so a record never straddles one. A record too
+// big for a block gets a block of its own that runs on over as many boundaries as it needs. Index
+// entries point at the start of every block. Files without the footer are all records, untagged,
+// as the first version of the store wrote them.
+const FOOTER_LEN: u64 = 32;
+const VERSIONED_FOOTER_MAGIC: u64 = 0xD1E6_05C0_FFEE_0E51;
+const HEADER_MAGIC: u32 = 0xD1E6_05C0;
+const HEADER_LEN: u64 = 5;
+// The format version written, and the only one read from versioned files.
+const FORMAT_VERSION: u8 = 1;
+// Record type tags. The tag is followed by the key, then the expiry (u64) of an expiring value,
+// then for values and merges the payload's length (u32) and the payload. A range tombstone's key
+// is the start of its range and its payload the encoded tombstone (see RangeTombstone::encode);
+// a table's range tombstones come before any of its other records, and aren't indexed.
+const VALUE_TAG: u8 = 0;
+const EXPIRING_TAG: u8 = 1;
+const TOMBSTONE_TAG: u8 = 2;
+const MERGE_TAG: u8 = 3;
+const RANGE_TOMBSTONE_TAG: u8 = 4;
+
+// Writes range tombstones, then records in ascending key order, to a new SSTable after its
+// header, each followed by a CRC32 of its bytes, then a sparse index of (key, offset) pairs, a
+// Bloom filter over every key, and a footer pointing at them.
+pub(super) struct TableWriter<K> {
+    file: BufWriter<File>,
+    compression: Compression,
+    // 0 writes records back to back, without blocks.
+    block_size: u64,
+    durability: Durability,
+    filename: String,
+    // The records of the block being filled, written out once it's full.
+    block: Vec<u8>,
+    // Where the next record goes, or for block-aligned tables where the current block starts.
+    offset: u64,
+    count: usize,
+    index: Vec<(K, u64)>,
+    hashes: Vec<u64>,
+    firstkey: Option<K>,
+    lastkey: Option<K>,
+}
+
+// Where the sections of an SSTable live. Files without a footer are all records, with no index
+// or filter, in which case the matching length is 0.
+struct Footer {
+    data_end: u64,
+    index_len: u64,
+    index_bytes: u64,
+    bloom_len: u64,
+    bloom_hashes: u32,
+    block_size: u64,
+    // Where the records start: past the header in versioned files, 0 in footerless ones.
+    data_start: u64,
+    // Set for files with the footer. Files without one were written before values were encoded
+    // with bincode, and may have been cut short by a crash mid-write.
+    versioned: bool,
+}
+
+// The first key and offset of every stretch of records an SSTable's index points at, in key
+// order, so a lookup can binary-search to the one stretch that could hold its key and read just
+// that: every INDEX_INTERVAL records, or in block-aligned tables every block. Loaded from the
+// index the first time a key is looked up in the file. Files without a footer have no index, so
+// theirs is built by hopping from one record header to the next.
+pub(super) struct TableOffsets<K> {
+    entries: Vec<(K, u64)>,
+    data_end: u64,
+    block_size: u64,
+    versioned: bool,
+}
+
+// The start of a record: its key bytes, value length and expiry, plus the length of the whole
+// record. The key starts after the tag, if the record has one.
+struct RecordHeader {
+    bytes: Vec<u8>,
+    key_start: usize,
+    key_len: usize,
+    len: u32,
+    expires: u64,
+    payload_len: u64,
+    record_len: u64,
+}
+
+// A key and its still-encoded value.
+pub(super) type RawEntry<K> = (K, Entry<Vec<u8>>);
+
+// A record as stored on disk, before its value is decoded.
+pub(super) enum RawRecord<K> {
+    // The key, the value's bytes and its expiry (0 for never).
+    Value(K, Vec<u8>, u64),
+    Tombstone(K),
+    // The key and its pending merge operands, still encoded as a whole.
+    Merge(K, Vec<u8>),
+    // Only found in the WAL: the next `count` records were written by one insert_batch.
+    BatchStart(u64),
+    // Only found in the WAL: the next record or batch belongs to the named column family.
+    Family(String),
+    // A delete_range in the WAL, or one of a table's range tombstones.
+    RangeDelete(RangeTombstone<K>),
+}
+
+// Reads records one after another from an SSTable's record section (or the WAL), tracking the
+// offset of each one so corruption can be pinned to a spot in the file.
+pub(super) struct RecordReader<R> {
+    pub(super) inner: R,
+    pub(super) filename: String,
+    pub(super) offset: u64,
+    pub(super) end: u64,
+    // 0 for sections without blocks. Otherwise records stop at block_end, and the reader skips
+    // the padding to the next block from there.
+    pub(super) block_size: u64,
+    pub(super) block_end: u64,
+    // Set for the records of versioned SSTables, which open with a type tag, are followed by a
+    // CRC32, and carry a codec id on their payloads.
+    pub(super) versioned: bool,
+    // Set for SSTables from before values were encoded with bincode, whose values are the bare
+    // bytes of a string. They're handed out with the length prefix bincode gives a string, so
+    // they decode like any other value, as a String or Vec<u8>.
+    pub(super) raw_values: bool,
+    // Set when the section runs to the end of a file a crash may have cut short. A record that
+    // runs past the end is then taken for a write that never finished: the section ends cleanly
+    // before it and every complete record is kept. Otherwise any record running past the end is
+    // an error.
+    pub(super) torn_tail: bool,
+}
+
+impl<R: Read> RecordReader<R> {
+    // A record running past the end of the section. Versioned files are always written out
+    // whole, so there this can only be corruption; otherwise it's reported as a short read.
+    fn truncated(&self) -> DingoError {
+        if self.versioned {
+            DingoError::Corruption { file: self.filename.clone(), offset: self.offset }
+        } else {
+            std::io::Error::from(ErrorKind::UnexpectedEof).into()
+        }
+    }
+
+    // Ends the section at the start of a record that runs past it, if the section may have a torn
+    // tail.
+    fn torn(&mut self) -> Result<Option<RecordHeader>, DingoError> {
+        if !self.torn_tail {
+            return Err(self.truncated());
+        }
+        self.end = self.offset;
+        Ok(None)
+    }
+
+    // Moves past the padding at the end of a block and the length at the start of the next one.
+    fn next_block(&mut self) -> Result<(), DingoError> {
+        let start = self.offset.next_multiple_of(self.block_size).min(self.end);
+        std::io::copy(&mut (&mut self.inner).take(start - self.offset), &mut std::io::sink())?;
+        self.offset = start;
+        if start == self.end {
+            return Ok(());
+        }
+        if start + 4 > self.end {
+            return Err(self.truncated());
+        }
+        let mut used = [0u8; 4];
+        self.inner.read_exact(&mut used)?;
+        self.offset += 4;
+        self.block_end = self.offset + u32::from_be_bytes(used) as u64;
+        if self.block_end > self.end {
+            return Err(DingoError::Corruption { file: self.filename.clone(), offset: start });
+        }
+        Ok(())
+    }
+
+    // Reads a record's key and value length, checking the whole record fits in the section.
+    // Returns None once the section is exhausted.
+    fn read_header<K: Key>(&mut self) -> Result<Option<RecordHeader>, DingoError> {
+        if self.block_size != 0 && self.offset >= self.block_end {
+            self.next_block()?;
+        }
+        if self.offset >= self.end {
+            return Ok(None);
+        }
+        let key_start = self.versioned as usize;
+        if self.offset + (key_start + K::PREFIX_LEN) as u64 > self.end {
+            return self.torn();
+        }
+        let mut record = vec![0u8; key_start + K::PREFIX_LEN];
+        self.inner.read_exact(&mut record)?;
+        let key_len = K::encoded_len(&record[key_start..]);
+        if self.versioned {
+            return self.read_tagged_header::<K>(record, key_len);
+        }
+        let mut header_len = key_len + 4;
+        if self.offset + header_len as u64 > self.end {
+            return self.torn();
+        }
+        record.resize(header_len, 0);
+        self.inner.read_exact(&mut record[K::PREFIX_LEN..])?;
+        let mut len = u32::from_be_bytes(record[key_len..header_len].try_into().unwrap());
+        let mut expires = 0;
+        if len == EXPIRING {
+            if self.offset + header_len as u64 + 12 > self.end {
+                return self.torn();
+            }
+            record.resize(header_len + 12, 0);
+            self.inner.read_exact(&mut record[header_len..])?;
+            expires = u64::from_be_bytes(record[header_len..header_len + 8].try_into().unwrap());
+            len = u32::from_be_bytes(record[header_len + 8..header_len + 12].try_into().unwrap());
+            header_len += 12;
+        }
+        // The length of a family name, merge operands or a range tombstone, which follows their
+        // marker.
+        let mut marked_len = 0;
+        if len == FAMILY || len == MERGE || len == RANGE_DELETE {
+            if self.offset + header_len as u64 + 4 > self.end {
+                return self.torn();
+            }
+            record.resize(header_len + 4, 0);
+            self.inner.read_exact(&mut record[header_len..])?;
+            marked_len = u32::from_be_bytes(record[header_len..header_len + 4].try_into().unwrap());
+            header_len += 4;
+        }
+        let payload_len = match len {
+            TOMBSTONE => 0,
+            BATCH => 8,
+            FAMILY | MERGE | RANGE_DELETE => marked_len as u64,
+            _ => len as u64,
+        };
+        let record_len = header_len as u64 + payload_len;
+        if self.offset + record_len > self.end {
+            return self.torn();
+        }
+        Ok(Some(RecordHeader { bytes: record, key_start: 0, key_len, len, expires, payload_len, record_len }))
+    }
+
+    // The rest of read_header for a record that opens with a type tag. The tag is mapped onto the
+    // length markers untagged records use, so the two read back the same way.
+    fn read_tagged_header<K: Key>(&mut self, mut record: Vec<u8>, key_len: usize) -> Result<Option<RecordHeader>, DingoError> {
+        let tag = record[0];
+        let fields_len = match tag {
+            VALUE_TAG | MERGE_TAG | RANGE_TOMBSTONE_TAG => 4,
+            EXPIRING_TAG => 12,
+            TOMBSTONE_TAG => 0,
+            _ => return Err(DingoError::Corruption { file: self.filename.clone(), offset: self.offset }),
+        };
+        let key_end = 1 + key_len;
+        let header_len = key_end + fields_len;
+        if self.offset + header_len as u64 > self.end {
+            return self.torn();
+        }
+        record.resize(header_len, 0);
+        self.inner.read_exact(&mut record[1 + K::PREFIX_LEN..])?;
+        let payload_len = match tag {
+            TOMBSTONE_TAG => 0,
+            _ => u32::from_be_bytes(record[header_len - 4..header_len].try_into().unwrap()),
+        };
+        let (len, expires) = match tag {
+            TOMBSTONE_TAG => (TOMBSTONE, 0),
+            MERGE_TAG => (MERGE, 0),
+            RANGE_TOMBSTONE_TAG => (RANGE_DELETE, 0),
+            EXPIRING_TAG => (payload_len, u64::from_be_bytes(record[key_end..key_end + 8].try_into().unwrap())),
+            _ => (payload_len, 0),
+        };
+        let record_len = header_len as u64 + payload_len as u64 + 4;
+        if self.offset + record_len > self.end {
+            return self.torn();
+        }
+        Ok(Some(RecordHeader { bytes: record, key_start: 1, key_len, len, expires, payload_len: payload_len as u64, record_len }))
+    }
+
+    // Reads the next raw record, returning its key and payload bytes (None for a tombstone), or
+    // None once the section is exhausted. Checksums are verified in versioned files.
+    pub(super) fn read_record<K: Key>(&mut self) -> Result<Option<RawRecord<K>>, DingoError> {
+        let Some(RecordHeader { bytes: mut record, key_start, key_len, len, expires, payload_len, record_len }) = self.read_header::<K>()? else {
+            return Ok(None);
+        };
+        let header_len = record.len();
+        record.resize(header_len + payload_len as usize, 0);
+        self.inner.read_exact(&mut record[header_len..])?;
+        if self.versioned {
+            let mut crc = [0u8; 4];
+            self.inner.read_exact(&mut crc)?;
+            if u32::from_be_bytes(crc) != crc32fast::hash(&record) {
+                return Err(DingoError::Corruption { file: self.filename.clone(), offset: self.offset });
+            }
+        }
+        self.offset += record_len;
+        let key = K::decode(&record[key_start..key_start + key_len])?;
+        match len {
+            TOMBSTONE => Ok(Some(RawRecord::Tombstone(key))),
+            BATCH => Ok(Some(RawRecord::BatchStart(u64::from_be_bytes(record[header_len..].try_into().unwrap())))),
+            FAMILY => Ok(Some(RawRecord::Family(String::from_utf8_lossy(&record[header_len..]).into_owned()))),
+            RANGE_DELETE => {
+                let corrupt = || DingoError::Corruption { file: self.filename.clone(), offset: self.offset - record_len };
+                let (tombstone, _) = RangeTombstone::decode(&record[header_len..], corrupt)?;
+                Ok(Some(RawRecord::RangeDelete(tombstone)))
+            }
+            _ => {
+                let payload = if self.versioned {
+                    let corrupt = || DingoError::Corruption { file: self.filename.clone(), offset: self.offset - record_len };
+                    compression::decompress(&record[header_len..], corrupt)?
+                } else {
+                    record.split_off(header_len)
+                };
+                match len {
+                    MERGE => Ok(Some(RawRecord::Merge(key, payload))),
+                    _ if self.raw_values => {
+                        let mut encoded = (payload.len() as u64).to_le_bytes().to_vec();
+                        encoded.extend_from_slice(&payload);
+                        Ok(Some(RawRecord::Value(key, encoded, expires)))
+                    }
+                    _ => Ok(Some(RawRecord::Value(key, payload, expires))),
+                }
+            }
+        }
+    }
+
+    // Reads and decodes the next record, skipping range tombstones. Returns None once the stream
+    // is exhausted; a tombstone comes back as a None value.
+    pub(super) fn try_deserialize<K: Key, V: Value>(&mut self) -> Result<Option<(K, Entry<V>)>, DingoError> {
+        loop {
+            return match self.read_record()? {
+                Some(RawRecord::RangeDelete(_)) => continue,
+                record => record.map(|record| record.decode(&self.filename)).transpose(),
+            };
+        }
+    }
+
+    // Like try_deserialize, but leaves the value undecoded.
+    pub(super) fn try_deserialize_key<K: Key>(&mut self) -> Result<Option<RawEntry<K>>, DingoError> {
+        loop {
+            return match self.read_record()? {
+                Some(RawRecord::Value(key, val, expires)) => Ok(Some((key, Entry { val: Some(val), expires, operands: Vec::new() }))),
+                Some(RawRecord::Tombstone(key)) => Ok(Some((key, Entry::new(None)))),
+                Some(RawRecord::Merge(key, payload)) => {
+                    Ok(Some((key, Entry { val: None, expires: 0, operands: bincode::deserialize(&payload)? })))
+                }
+                Some(RawRecord::BatchStart(_) | RawRecord::Family(_) | RawRecord::RangeDelete(_)) => continue,
+                None => Ok(None),
+            };
+        }
+    }
+}
+
+impl RecordReader<BufReader<File>> {
+    // Reads just the key of the next record and skips the rest of it unverified, along with any
+    // range tombstones before it. Returns the record's offset too.
+    fn skip_record<K: Key>(&mut self) -> Result<Option<(K, u64)>, DingoError> {
+        loop {
+            let offset = self.offset;
+            let Some(header) = self.read_header::<K>()? else {
+                return Ok(None);
+            };
+            self.inner.seek_relative((header.record_len - header.bytes.len() as u64) as i64)?;
+            self.offset += header.record_len;
+            if header.len != RANGE_DELETE {
+                return Ok(Some((K::decode(&header.bytes[header.key_start..header.key_start + header.key_len])?, offset)));
+            }
+        }
+    }
+}
+
+impl<K: Key> RawRecord<K> {
+    // Decodes a record read from `file`.
+    pub(super) fn decode<V: Value>(self, file: &str) -> Result<(K, Entry<V>), DingoError> {
+        match self {
+            RawRecord::Value(key, val_bytes, expires) => {
+                let val = decode_value(&val_bytes, &key, file)?;
+                Ok((key, Entry { val: Some(val), expires, operands: Vec::new() }))
+            }
+            RawRecord::Tombstone(key) => Ok((key, Entry::new(None))),
+            RawRecord::Merge(key, payload) => {
+                let encoded: Vec<Vec<u8>> = bincode::deserialize(&payload)?;
+                let entry = Entry { val: None, expires: 0, operands: encoded }.decode(&key, file)?;
+                Ok((key, entry))
+            }
+            RawRecord::BatchStart(_) | RawRecord::Family(_) | RawRecord::RangeDelete(_) => Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "WAL marker outside the WAL",
+            )
+            .into()),
+        }
+    }
+}
+
+impl<K: Key> TableWriter<K> {
+    // A block_size of 0 writes the records without blocks.
+    pub(super) fn create(data_fname: &str, compression: Compression, block_size: u32, durability: Durability) -> Result<TableWriter<K>, DingoError> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(data_fname)?;
+        let mut file = BufWriter::new(file);
+        file.write_all(&HEADER_MAGIC.to_be_bytes())?;
+        file.write_all(&[FORMAT_VERSION])?;
+        let offset = data_start(block_size as u64);
+        file.write_all(&vec![0u8; (offset - HEADER_LEN) as usize])?;
+        Ok(TableWriter {
+            file,
+            compression,
+            block_size: block_size as u64,
+            durability,
+            filename: data_fname.to_string(),
+            block: Vec::new(),
+            offset,
+            count: 0,
+            index: Vec::new(),
+            hashes: Vec::new(),
+            firstkey: None,
+            lastkey: None,
+        })
+    }
+
+    // Range tombstones all have to be added before the first record.
+    pub(super) fn add_range_tombstone(&mut self, tombstone: &RangeTombstone<K>) -> Result<(), DingoError> {
+        debug_assert_eq!(self.count, 0);
+        self.write_record(&encode_tagged(RANGE_TOMBSTONE_TAG, &tombstone.start, 0, Some(&tombstone.encode())), None)
+    }
+
+    pub(super) fn add<V: Serialize>(&mut self, key: &K, entry: &Entry<V>) -> Result<(), DingoError> {
+        // Payloads always carry the codec id, compressed or not.
+        let bytes = match entry.val.as_ref().map(bincode::serialize).transpose()? {
+            _ if entry.is_merge() => encode_tagged(MERGE_TAG, key, 0, Some(&self.compression.compress(&operands_payload(&entry.operands)?))),
+            Some(payload) if entry.expires != 0 => encode_tagged(EXPIRING_TAG, key, entry.expires, Some(&self.compression.compress(&payload))),
+            Some(payload) => encode_tagged(VALUE_TAG, key, 0, Some(&self.compression.compress(&payload))),
+            None => encode_tagged(TOMBSTONE_TAG, key, 0, None),
+        };
+        if self.firstkey.is_none() {
+            self.firstkey = Some(key.clone());
+        }
+        self.lastkey = Some(key.clone());
+        self.hashes.push(key.bloom_hash());
+        self.count += 1;
+        self.write_record(&bytes, Some(key))
+    }
+
+    // Writes out a record and its CRC32, indexing it under `key` if it's due an index entry. Range
+    // tombstones have no key to index.
+    fn write_record(&mut self, bytes: &[u8], key: Option<&K>) -> Result<(), DingoError> {
+        let crc = crc32fast::hash(bytes).to_be_bytes();
+        if self.block_size == 0 {
+            if let Some(key) = key.filter(|_| (self.count - 1).is_multiple_of(INDEX_INTERVAL)) {
+                self.index.push((key.clone(), self.offset));
+            }
+            self.file.write_all(bytes)?;
+            self.file.write_all(&crc)?;
+            self.offset += bytes.len() as u64 + 4;
+            return Ok(());
+        }
+        let record_len = bytes.len() as u64 + 4;
+        if !self.block.is_empty() && 4 + self.block.len() as u64 + record_len > self.block_size {
+            self.end_block()?;
+        }
+        // The first record's block is indexed even if range tombstones started it.
+        if let Some(key) = key.filter(|_| self.block.is_empty() || self.count == 1) {
+            self.index.push((key.clone(), self.offset));
+        }
+        self.block.extend_from_slice(bytes);
+        self.block.extend_from_slice(&crc);
+        Ok(())
+    }
+
+    // Bytes of records added so far, not counting the header.
+    pub(super) fn len(&self) -> u64 {
+        self.offset + self.block.len() as u64 - data_start(self.block_size)
+    }
+
+    // Writes out the current block, padded up to the next block boundary.
+    fn end_block(&mut self) -> Result<(), DingoError> {
+        let len = 4 + self.block.len() as u64;
+        let padded = len.next_multiple_of(self.block_size);
+        self.file.write_all(&(self.block.len() as u32).to_be_bytes())?;
+        self.file.write_all(&self.block)?;
+        self.file.write_all(&vec![0u8; (padded - len) as usize])?;
+        self.offset += padded;
+        self.block.clear();
+        Ok(())
+    }
+
+    // Returns the first and last keys written (None if the table is empty) and the table's
+    // filter.
+    pub(super) fn finish(mut self) -> Result<(Option<(K, K)>, Bloom), DingoError> {
+        if !self.block.is_empty() {
+            self.end_block()?;
+        }
+        for (key, offset) in &self.index {
+            self.file.write_all(&key.encode())?;
+            self.file.write_all(&offset.to_be_bytes())?;
+        }
+        let mut bloom = Bloom::new(self.hashes.len());
+        for hash in &self.hashes {
+            bloom.insert(*hash);
+        }
+        self.file.write_all(bloom.as_bytes())?;
+        self.file.write_all(&(self.block_size as u32).to_be_bytes())?;
+        self.file.write_all(&self.offset.to_be_bytes())?;
+        self.file.write_all(&(self.index.len() as u32).to_be_bytes())?;
+        self.file.write_all(&(bloom.as_bytes().len() as u32).to_be_bytes())?;
+        self.file.write_all(&bloom.hashes().to_be_bytes())?;
+        self.file.write_all(&VERSIONED_FOOTER_MAGIC.to_be_bytes())?;
+        let file = self.file.into_inner().map_err(|e| e.into_error())?;
+        if self.durability.sync_files() {
+            file.sync_all()?;
+        }
+        self.durability.sync_dir_of(&self.filename)?;
+        Ok((self.firstkey.zip(self.lastkey), bloom))
+    }
+}
+
+impl Footer {
+    // Files written before the index existed have no footer, so all of the file is records.
+    // Versioned files of a format version other than FORMAT_VERSION are refused.
+    fn read(f: &mut File, filename: &str) -> Result<Footer, DingoError> {
+        let file_len = f.metadata()?.len();
+        let no_footer = Footer { data_end: file_len, index_len: 0, index_bytes: 0, bloom_len: 0, bloom_hashes: 0, block_size: 0, data_start: 0, versioned: false };
+        if file_len < FOOTER_LEN {
+            return Ok(no_footer);
+        }
+        let mut footer = [0u8; FOOTER_LEN as usize];
+        f.seek(SeekFrom::Start(file_len - FOOTER_LEN))?;
+        f.read_exact(&mut footer)?;
+        if u64::from_be_bytes(footer[24..32].try_into().unwrap()) != VERSIONED_FOOTER_MAGIC {
+            return Ok(no_footer);
+        }
+        check_header(f, filename)?;
+        let block_size = u32::from_be_bytes(footer[0..4].try_into().unwrap()) as u64;
+        let index_offset = u64::from_be_bytes(footer[4..12].try_into().unwrap());
+        let index_len = u32::from_be_bytes(footer[12..16].try_into().unwrap()) as u64;
+        let bloom_len = u32::from_be_bytes(footer[16..20].try_into().unwrap()) as u64;
+        let bloom_hashes = u32::from_be_bytes(footer[20..24].try_into().unwrap());
+        let data_start = data_start(block_size);
+        if index_offset < data_start || index_offset.checked_add(bloom_len + FOOTER_LEN).is_none_or(|end| end > file_len) {
+            return Err(DingoError::Corruption { file: filename.to_string(), offset: file_len - FOOTER_LEN });
+        }
+        let index_bytes = file_len - FOOTER_LEN - bloom_len - index_offset;
+        Ok(Footer { data_end: index_offset, index_len, index_bytes, bloom_len, bloom_hashes, block_size, data_start, versioned: true })
+    }
+
+    fn read_index<K: Key>(&self, f: &mut File, filename: &str) -> Result<Vec<(K, u64)>, DingoError> {
+        let mut index_bytes = vec![0u8; self.index_bytes as usize];
+        f.seek(SeekFrom::Start(self.data_end))?;
+        f.read_exact(&mut index_bytes)?;
+        let mut index = Vec::with_capacity(self.index_len as usize);
+        let mut rest = &index_bytes[..];
+        for _ in 0..self.index_len {
+            let key_len = rest.get(..K::PREFIX_LEN).map_or(usize::MAX, K::encoded_len);
+            if key_len.saturating_add(8) > rest.len() {
+                let offset = self.data_end + (index_bytes.len() - rest.len()) as u64;
+                return Err(DingoError::Corruption { file: filename.to_string(), offset });
+            }
+            let key = K::decode(&rest[..key_len])?;
+            index.push((key, u64::from_be_bytes(rest[key_len..key_len + 8].try_into().unwrap())));
+            rest = &rest[key_len + 8..];
+        }
+        Ok(index)
+    }
+
+    fn read_bloom(&self, f: &mut File) -> Result<Option<Bloom>, DingoError> {
+        if self.bloom_len == 0 {
+            return Ok(None);
+        }
+        let mut bits = vec![0u8; self.bloom_len as usize];
+        f.seek(SeekFrom::Start(self.data_end + self.index_bytes))?;
+        f.read_exact(&mut bits)?;
+        Ok(Some(Bloom::from_bytes(bits, self.bloom_hashes)))
+    }
+}
+
+// Where the records of a versioned SSTable start: right after the header, or on the first block
+// boundary past it.
+fn data_start(block_size: u64) -> u64 {
+    match block_size {
+        0 => HEADER_LEN,
+        block_size => HEADER_LEN.next_multiple_of(block_size),
+    }
+}
+
+// Checks the header at the start of a versioned SSTable.
+fn check_header(f: &mut File, filename: &str) -> Result<(), DingoError> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    f.seek(SeekFrom::Start(0))?;
+    f.read_exact(&mut header)?;
+    if u32::from_be_bytes(header[0..4].try_into().unwrap()) != HEADER_MAGIC {
+        return Err(DingoError::Corruption { file: filename.to_string(), offset: 0 });
+    }
+    if header[4] != FORMAT_VERSION {
+        return Err(DingoError::UnsupportedVersion { file: filename.to_string(), version: header[4] });
+    }
+    Ok(())
+}
+
+// A record of a versioned SSTable, opening with its type tag. A None payload is a tombstone.
+fn encode_tagged<K: Key>(tag: u8, key: &K, expires: u64, payload: Option<&[u8]>) -> Vec<u8> {
+    let mut bytes = vec![tag];
+    bytes.extend_from_slice(&key.encode());
+    if tag == EXPIRING_TAG {
+        bytes.extend_from_slice(&expires.to_be_bytes());
+    }
+    if let Some(payload) = payload {
+        bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(payload);
+    }
+    bytes
+}
+
+// Opens an SSTable limited to its record section, positioned at the start of the indexed
+// block that would hold `from` (or the first record if there's no such block or no `from`).
+pub(super) fn open_table<K: Key>(filename: &str, from: Option<&K>) -> Result<RecordReader<BufReader<File>>, DingoError> {
+    let mut f = File::open(filename)?;
+    let footer = Footer::read(&mut f, filename)?;
+    let index = footer.read_index::<K>(&mut f, filename)?;
+    let block = from.map_or(0, |from| index.partition_point(|(k, _)| k <= from));
+    let start = if block == 0 { footer.data_start } else { index[block - 1].1 };
+    f.seek(SeekFrom::Start(start))?;
+    Ok(RecordReader {
+        inner: BufReader::new(f),
+        filename: filename.to_string(),
+        offset: start,
+        end: footer.data_end,
+        block_size: footer.block_size,
+        block_end: start,
+        versioned: footer.versioned,
+        raw_values: !footer.versioned,
+        torn_tail: !footer.versioned,
+    })
+}
+
+// The range tombstones at the start of an SSTable.
+pub(super) fn range_tombstones<K: Key>(filename: &str) -> Result<Vec<RangeTombstone<K>>, DingoError> {
+    let mut reader = open_table::<K>(filename, None)?;
+    let mut tombstones = Vec::new();
+    while let Some(RawRecord::RangeDelete(tombstone)) = reader.read_record()? {
+        tombstones.push(tombstone);
+    }
+    Ok(tombstones)
+}
+
+// Reads an SSTable's records from last to first, a chunk at a time: the records from one index
+// entry up to the next are read front to back, then handed out in reverse. Files without an index
+// are read as a single chunk.
+pub(super) struct ReverseReader<K> {
+    reader: RecordReader<BufReader<File>>,
+    // Where each chunk still to be read starts, in file order, and where the last of them ends.
+    starts: Vec<u64>,
+    end: u64,
+    // What's left of the chunk being handed out; the last record is next.
+    chunk: Vec<RawRecord<K>>,
+}
+
+impl<K: Key> ReverseReader<K> {
+    // Like RecordReader::try_deserialize, going backwards.
+    pub(super) fn try_deserialize<V: Value>(&mut self) -> Result<Option<(K, Entry<V>)>, DingoError> {
+        let record = self.next_record()?;
+        record.map(|record| record.decode(&self.reader.filename)).transpose()
+    }
+
+    fn next_record(&mut self) -> Result<Option<RawRecord<K>>, DingoError> {
+        loop {
+            if let Some(record) = self.chunk.pop() {
+                return Ok(Some(record));
+            }
+            let Some(start) = self.starts.pop() else {
+                return Ok(None);
+            };
+            self.reader.inner.seek(SeekFrom::Start(start))?;
+            self.reader.offset = start;
+            self.reader.end = self.end;
+            self.reader.block_end = start;
+            while let Some(record) = self.reader.read_record()? {
+                if !matches!(record, RawRecord::RangeDelete(_)) {
+                    self.chunk.push(record);
+                }
+            }
+            self.end = start;
+        }
+    }
+}
+
+// Opens an SSTable to be read backwards, from the end of the indexed block that would hold `to`
+// (or from the last record if there's no such block or no `to`).
+pub(super) fn open_table_rev<K: Key>(filename: &str, to: Option<&K>) -> Result<ReverseReader<K>, DingoError> {
+    let mut f = File::open(filename)?;
+    let footer = Footer::read(&mut f, filename)?;
+    let index = footer.read_index::<K>(&mut f, filename)?;
+    let blocks = to.map_or(index.len(), |to| index.partition_point(|(k, _)| k <= to));
+    let end = index.get(blocks).map_or(footer.data_end, |(_, offset)| *offset);
+    let mut starts: Vec<u64> = index[..blocks].iter().map(|(_, offset)| *offset).collect();
+    if starts.first() != Some(&footer.data_start) {
+        starts.insert(0, footer.data_start);
+    }
+    Ok(ReverseReader {
+        reader: RecordReader {
+            inner: BufReader::new(f),
+            filename: filename.to_string(),
+            offset: footer.data_start,
+            end,
+            block_size: footer.block_size,
+            block_end: footer.data_start,
+            versioned: footer.versioned,
+            raw_values: !footer.versioned,
+            torn_tail: !footer.versioned,
+        },
+        starts,
+        end,
+        chunk: Vec::new(),
+    })
+}
+
+// Scans the stretch of records seek_in narrowed a lookup down to for the key.
+fn find_record<K: Key, R: Read>(mut reader: RecordReader<R>, key: &K) -> Result<Option<Entry<Vec<u8>>>, DingoError> {
+    loop {
+        match reader.try_deserialize_key::<K>()? {
+            Some((k, val)) if k == *key => return Ok(Some(val)),
+            Some((k, _)) if k < *key => continue,
+            _ => return Ok(None),
+        }
+    }
+}
+
+// Which SSTables are still being read by a snapshot, and which of those compaction has already
+// replaced.
+#[derive(Default)]
+struct Refs {
+    counts: HashMap<String, usize>,
+    retired: HashSet<String>,
+}
+
+// What the store keeps in memory about its SSTables, shared with its snapshots so they can go on
+// reading files the store itself has moved past.
+pub(super) struct Tables<K> {
+    // Bloom filter per SSTable filename, None for files written without one.
+    blooms: Mutex<HashMap<String, Option<Bloom>>>,
+    // Record offsets per SSTable filename, dropped along with the file.
+    offsets: Mutex<HashMap<String, Arc<TableOffsets<K>>>>,
+    // Set when lookups read from memory maps, kept per SSTable filename until the file is
+    // deleted.
+    mmap: bool,
+    maps: Mutex<HashMap<String, Arc<Mmap>>>,
+    refs: Mutex<Refs>,
+    pub(super) counters: Arc<Counters>,
+}
+
+impl<K: Key> Tables<K> {
+    pub(super) fn new(counters: Arc<Counters>, mmap: bool) -> Tables<K> {
+        Tables {
+            blooms: Mutex::new(HashMap::new()),
+            offsets: Mutex::new(HashMap::new()),
+            mmap,
+            maps: Mutex::new(HashMap::new()),
+            refs: Mutex::new(Refs::default()),
+            counters,
+        }
+    }
+
+    // Registers the filter of a table that was just written, sparing a read of its footer.
+    pub(super) fn add(&self, filename: &str, bloom: Bloom) -> Result<(), DingoError> {
+        self.blooms.lock()?.insert(filename.to_string(), Some(bloom));
+        Ok(())
+    }
+
+    // Deletes a table that is no longer part of the store, or leaves it to the last snapshot
+    // still reading it.
+    pub(super) fn retire(&self, filename: &str) -> Result<(), DingoError> {
+        let mut refs = self.refs.lock()?;
+        if refs.counts.contains_key(filename) {
+            refs.retired.insert(filename.to_string());
+            return Ok(());
+        }
+        self.remove(filename)
+    }
+
+    pub(super) fn acquire(&self, filename: &str) -> Result<(), DingoError> {
+        *self.refs.lock()?.counts.entry(filename.to_string()).or_default() += 1;
+        Ok(())
+    }
+
+    // Drops a snapshot's hold on a table, deleting it if compaction retired it in the meantime.
+    pub(super) fn release(&self, filename: &str) -> Result<(), DingoError> {
+        let mut refs = self.refs.lock()?;
+        let Some(count) = refs.counts.get_mut(filename) else {
+            return Ok(());
+        };
+        *count -= 1;
+        if *count > 0 {
+            return Ok(());
+        }
+        refs.counts.remove(filename);
+        if refs.retired.remove(filename) {
+            self.remove(filename)?;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, filename: &str) -> Result<(), DingoError> {
+        std::fs::remove_file(filename)?;
+        self.blooms.lock()?.remove(filename);
+        self.offsets.lock()?.remove(filename);
+        // Lookups still reading the old mapping hold their own Arc; it's unmapped once they're
+        // done.
+        self.maps.lock()?.remove(filename);
+        Ok(())
+    }
+
+    // The SSTable's memory map, created the first time a lookup reads the file. Only tables that
+    // are in the store are ever looked up, and they only get there once they've been written
+    // out in full, so a table is never mapped while it's still being written.
+    fn mapping(&self, filename: &str) -> Result<Arc<Mmap>, DingoError> {
+        if let Some(map) = self.maps.lock()?.get(filename) {
+            return Ok(Arc::clone(map));
+        }
+        let file = File::open(filename)?;
+        // SSTables are never modified once written, only deleted, which leaves existing mappings
+        // intact.
+        let map = Arc::new(unsafe { Mmap::map(&file)? });
+        // Another lookup may have mapped the file meanwhile; the first mapping in is kept.
+        let mut maps = self.maps.lock()?;
+        let map = maps.entry(filename.to_string()).or_insert_with(|| {
+            self.counters.mmaps.fetch_add(1, Ordering::Relaxed);
+            map
+        });
+        Ok(Arc::clone(map))
+    }
+
+    // Reads the footer of an SSTable whose filter or offsets a lookup needs.
+    fn read_footer(&self, f: &mut File, filename: &str) -> Result<Footer, DingoError> {
+        self.counters.footer_reads.fetch_add(1, Ordering::Relaxed);
+        Footer::read(f, filename)
+    }
+
+    // Loads the SSTable's filter unless it's already in memory. The file is read without the lock
+    // held, so lookups in other tables don't wait on it. A table is only removed once no lookup
+    // can reach it, so what's loaded here is never put back after its table is gone.
+    fn load_bloom(&self, filename: &str) -> Result<(), DingoError> {
+        if self.blooms.lock()?.contains_key(filename) {
+            return Ok(());
+        }
+        let mut f = File::open(filename)?;
+        let footer = self.read_footer(&mut f, filename)?;
+        let bloom = footer.read_bloom(&mut f)?;
+        self.blooms.lock()?.entry(filename.to_string()).or_insert(bloom);
+        Ok(())
+    }
+
+    // Loads the SSTable's filter and offsets ahead of its first lookup.
+    pub(super) fn preload(&self, filename: &str) -> Result<(), DingoError> {
+        self.load_bloom(filename)?;
+        self.table_offsets(filename)?;
+        Ok(())
+    }
+
+    // Checks the SSTable's Bloom filter, loading it from the footer the first time the file is
+    // consulted. Files without a filter always have to be scanned.
+    pub(super) fn may_contain(&self, filename: &str, key: &K) -> Result<bool, DingoError> {
+        let mut blooms = self.blooms.lock()?;
+        if !blooms.contains_key(filename) {
+            drop(blooms);
+            self.load_bloom(filename)?;
+            blooms = self.blooms.lock()?;
+        }
+        let Some(Some(bloom)) = blooms.get(filename) else {
+            return Ok(true);
+        };
+        let hit = bloom.contains(key.bloom_hash());
+        let counter = if hit { &self.counters.bloom_hits } else { &self.counters.bloom_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        Ok(hit)
+    }
+
+    // The SSTable's offsets, loaded the first time they're needed. Like filters, they're read
+    // without the lock held.
+    pub(super) fn table_offsets(&self, filename: &str) -> Result<Arc<TableOffsets<K>>, DingoError> {
+        if let Some(table) = self.offsets.lock()?.get(filename) {
+            return Ok(Arc::clone(table));
+        }
+        let mut f = File::open(filename)?;
+        let footer = self.read_footer(&mut f, filename)?;
+        let table = if footer.versioned {
+            TableOffsets { entries: footer.read_index(&mut f, filename)?, data_end: footer.data_end, block_size: footer.block_size, versioned: true }
+        } else {
+            let mut reader = open_table::<K>(filename, None)?;
+            let mut entries = Vec::new();
+            let mut count = 0;
+            while let Some(entry) = reader.skip_record()? {
+                if count % INDEX_INTERVAL == 0 {
+                    entries.push(entry);
+                }
+                count += 1;
+            }
+            // The records end before any a crash left half written.
+            TableOffsets { entries, data_end: reader.end, block_size: 0, versioned: false }
+        };
+        let mut offsets = self.offsets.lock()?;
+        let table = offsets.entry(filename.to_string()).or_insert_with(|| Arc::new(table));
+        Ok(Arc::clone(table))
+    }
+
+    // The table's last key, read from the last stretch of records its offsets point at. Only needed
+    // for tables of stores from before the manifest existed.
+    pub(super) fn last_key(&self, filename: &str) -> Result<Option<K>, DingoError> {
+        let table = self.table_offsets(filename)?;
+        let Some((_, start)) = table.entries.last() else {
+            return Ok(None);
+        };
+        let mut f = File::open(filename)?;
+        f.seek(SeekFrom::Start(*start))?;
+        let mut reader = RecordReader {
+            inner: BufReader::new(f),
+            filename: filename.to_string(),
+            offset: *start,
+            end: table.data_end,
+            block_size: table.block_size,
+            block_end: *start,
+            versioned: table.versioned,
+            raw_values: !table.versioned,
+            torn_tail: false,
+        };
+        let mut last = None;
+        while let Some((key, _)) = reader.try_deserialize_key::<K>()? {
+            last = Some(key);
+        }
+        Ok(last)
+    }
+
+    // Some means the file holds a record for the key, which may be a tombstone. Records are
+    // written in key order, so a binary search over the file's offsets finds the one stretch of
+    // records to scan.
+    pub(super) fn seek_key(&self, filename: &str, key: &K) -> Result<Option<Entry<Vec<u8>>>, DingoError> {
+        let table = self.table_offsets(filename)?;
+        self.seek_in(&table, filename, &mut None, key)
+    }
+
+    // seek_key against an already loaded offset table. The file is only opened once a record
+    // needs reading, and is left open in `file` for further lookups, unless the table is read
+    // through its memory map instead. The stretch the search narrows it down to is read in one go
+    // and scanned.
+    pub(super) fn seek_in(&self, table: &TableOffsets<K>, filename: &str, file: &mut Option<File>, key: &K) -> Result<Option<Entry<Vec<u8>>>, DingoError> {
+        let next = table.entries.partition_point(|(k, _)| k <= key);
+        if next == 0 {
+            return Ok(None);
+        }
+        let start = table.entries[next - 1].1;
+        let end = table.entries.get(next).map_or(table.data_end, |(_, offset)| *offset);
+        if self.mmap {
+            let map = self.mapping(filename)?;
+            let reader = RecordReader {
+                inner: map.get(start as usize..end as usize).ok_or_else(|| DingoError::Corruption {
+                    file: filename.to_string(),
+                    offset: start,
+                })?,
+                filename: filename.to_string(),
+                offset: start,
+                end,
+                block_size: table.block_size,
+                block_end: start,
+                versioned: table.versioned,
+                raw_values: !table.versioned,
+                torn_tail: false,
+            };
+            return find_record(reader, key);
+        }
+        let file = match file {
+            Some(file) => file,
+            None => file.insert(File::open(filename)?),
+        };
+        file.seek(SeekFrom::Start(start))?;
+        let reader = RecordReader {
+            inner: BufReader::with_capacity((end - start) as usize, file),
+            filename: filename.to_string(),
+            offset: start,
+            end,
+            block_size: table.block_size,
+            block_end: start,
+            versioned: table.versioned,
+            raw_values: !table.versioned,
+            torn_tail: false,
+        };
+        find_record(reader, key)
+    }
+
+    // The same key can live in several of `files`, so walk them newest first and let the first
+    // hit shadow anything older, unless it's a pending merge, which is laid over what turns up
+    // further down. A table without the key whose range tombstones cover it counts as a hit on a
+    // tombstone. Only tables whose first and last keys take in the key are consulted. A key none
+    // of them holds comes back as a tombstone.
+    pub(super) fn get<V: Value>(&self, files: &[TableMeta<K>], key: &K, operator: Option<MergeOperator<V>>) -> Result<Entry<V>, DingoError> {
+        let mut merges = Vec::new();
+        for table in files.iter().rev() {
+            if !table.spans(key) {
+                continue;
+            }
+            let found = match self.may_contain(&table.filename, key)? {
+                false => None,
+                true => self.seek_key(&table.filename, key)?,
+            };
+            match found.map(|entry| entry.decode(key, &table.filename)).transpose()? {
+                Some(entry) if entry.is_merge() => merges.push(entry),
+                Some(entry) => return stack(merges, entry, operator),
+                None if table.deletes(key)? => return stack(merges, Entry::new(None), operator),
+                None => {}
+            }
+        }
+        stack(merges, Entry::new(None), operator)
+    }
+}
@@ -0,0 +1,87 @@
+use std::ops::{Bound, RangeBounds};
+
+use super::{DingoError, Key};
+
+// Deletes every key in a range, as a single record, in the memtable or SSTable it's in and
+// everything older. Newer writes to keys in the range are unaffected: delete_range drops what the
+// memtable held for the range, so within one memtable or SSTable every entry is newer than the
+// range tombstones alongside it. Both ends are keys; an unbounded end is narrowed down to the
+// family's first or last key when the range is deleted.
+#[derive(Clone)]
+pub(super) struct RangeTombstone<K> {
+    pub(super) start: K,
+    pub(super) start_excluded: bool,
+    pub(super) end: K,
+    pub(super) end_excluded: bool,
+}
+
+impl<K: Key> RangeTombstone<K> {
+    pub(super) fn covers(&self, key: &K) -> bool {
+        self.bounds().contains(key)
+    }
+
+    pub(super) fn bounds(&self) -> (Bound<&K>, Bound<&K>) {
+        let bound = |key, excluded| if excluded { Bound::Excluded(key) } else { Bound::Included(key) };
+        (bound(&self.start, self.start_excluded), bound(&self.end, self.end_excluded))
+    }
+
+    // Whether no key could be in the range.
+    pub(super) fn is_empty(&self) -> bool {
+        self.start > self.end || (self.start == self.end && (self.start_excluded || self.end_excluded))
+    }
+
+    // Flags (bit 0 set for an excluded start, bit 1 for an excluded end), then the encoded start
+    // and end keys.
+    pub(super) fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![self.start_excluded as u8 | (self.end_excluded as u8) << 1];
+        bytes.extend_from_slice(&self.start.encode());
+        bytes.extend_from_slice(&self.end.encode());
+        bytes
+    }
+
+    // Decodes a tombstone from the front of `bytes`, returning how many bytes it took up. `corrupt`
+    // reports bytes that run out early.
+    pub(super) fn decode(bytes: &[u8], corrupt: impl Fn() -> DingoError) -> Result<(RangeTombstone<K>, usize), DingoError> {
+        let key_at = |pos: usize| {
+            let key_len = bytes.get(pos..pos + K::PREFIX_LEN).map_or(usize::MAX, K::encoded_len);
+            let key_bytes = bytes.get(pos..pos.saturating_add(key_len)).ok_or_else(&corrupt)?;
+            Ok::<_, DingoError>((K::decode(key_bytes)?, key_len))
+        };
+        let flags = *bytes.first().ok_or_else(&corrupt)?;
+        let (start, start_len) = key_at(1)?;
+        let (end, end_len) = key_at(1 + start_len)?;
+        let tombstone = RangeTombstone { start, start_excluded: flags & 1 != 0, end, end_excluded: flags & 2 != 0 };
+        Ok((tombstone, 1 + start_len + end_len))
+    }
+}
+
+// Whether any of `tombstones` covers the key.
+pub(super) fn deleted<K: Key>(tombstones: &[RangeTombstone<K>], key: &K) -> bool {
+    tombstones.iter().any(|tombstone| tombstone.covers(key))
+}
+
+// The keys a table could touch: from its first to its last record, widened to take in every
+// range its tombstones cover. None if it has neither records nor tombstones.
+pub(super) fn span<K: Key>(records: Option<(K, K)>, tombstones: &[RangeTombstone<K>]) -> Option<(K, K)> {
+    let starts = tombstones.iter().map(|tombstone| &tombstone.start);
+    let ends = tombstones.iter().map(|tombstone| &tombstone.end);
+    let first = records.as_ref().map(|(first, _)| first).into_iter().chain(starts).min()?;
+    let last = records.as_ref().map(|(_, last)| last).into_iter().chain(ends).max()?;
+    Some((first.clone(), last.clone()))
+}
+
+// The tombstone for `range`, with an unbounded end standing in for `first` or `last`. None if
+// an unbounded end has no key to stand in for.
+pub(super) fn bounded<K: Key>(range: impl RangeBounds<K>, first: Option<K>, last: Option<K>) -> Option<RangeTombstone<K>> {
+    let (start, start_excluded) = match range.start_bound().cloned() {
+        Bound::Included(start) => (start, false),
+        Bound::Excluded(start) => (start, true),
+        Bound::Unbounded => (first?, false),
+    };
+    let (end, end_excluded) = match range.end_bound().cloned() {
+        Bound::Included(end) => (end, false),
+        Bound::Excluded(end) => (end, true),
+        Bound::Unbounded => (last?, false),
+    };
+    Some(RangeTombstone { start, start_excluded, end, end_excluded })
+}
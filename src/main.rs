@@ -1,19 +1,16 @@
-mod dingostore;
-use dingostore::DingoStore;
+use dingodb::dingostore::DingoStore;
 use std::time::Instant;
 use rand::{thread_rng, Rng};
 use rand::distributions::Alphanumeric;
-use tokio;
 
 // LIMITATIONS:
-// - Currently no compaction. This means that as writes increase, so will the # of SSTables and
-// reads will slow down. Will implement this soon
-// - Currently no Write Ahead Log (WAL), so upon death of the server, KVs are lost. I will
-// implement this soon.
-// - Keys must be of type u64. String keys are nice, but they lead to variable length keys, which
-// can slow down reads a bit. I'm thinking about implementing them anyways and having a type switch
-// to discern between which type of key is used.
-// - Not aligned to SSD blocks. I wanted to finish implementing this but want to do more testing.
+// - Compaction is a full size-tiered merge once more than COMPACT_LIM SSTables pile up unless
+// leveled or small-runs compaction is picked, or compact_now() is called (see also
+// compaction_interval). Until then, reads slow down as the # of SSTables grows.
+// - The Write Ahead Log (WAL) is only fsynced per insert with Durability::SyncEveryWrite, so
+// otherwise the last few KVs can still be lost if the machine dies.
+// - Keys are u64 by default. String keys are supported through the Key trait, but being variable
+// length they slow down reads a bit.
 // - I haven't tested this as much as I'd like
 // - Error handling and logging aren't ideal
 
@@ -27,7 +24,7 @@ fn generate_random_string(len: usize) -> String {
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut ds = DingoStore::new("dingostore");
+    let mut ds = DingoStore::new("dingostore")?;
     let start_time = Instant::now();
 
     // Write a large amount of data
@@ -43,7 +40,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         key_value_pairs.push((key, value.clone()));
         
         let write_start = Instant::now();
-        ds.insert(key, value);
+        ds.insert(key, value)?;
         total_write_time += write_start.elapsed();
         
         if (i + 1) % 10000 == 0 {
@@ -64,7 +61,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     for (i, (key, expected_value)) in key_value_pairs.iter().enumerate().take(total_reads) {
         let read_start = Instant::now();
-        match ds.get(*key) {
+        match ds.get(*key)? {
             Some(value) => {
                 total_read_time += read_start.elapsed();
                 if value == *expected_value {
@@ -109,5 +106,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Average write time: {:?}", total_write_time / total_writes as u32);
     println!("Total read time: {:?}", read_time);
     println!("Average read time: {:?}", total_read_time / total_reads as u32);
+    println!("Store stats: {:?}", ds.stats()?);
     Ok(())
 }
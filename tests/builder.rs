@@ -0,0 +1,69 @@
+mod common;
+
+use dingodb::dingostore::{DingoStore, DingoStoreBuilder};
+
+#[test]
+fn a_tiny_memtable_flushes_after_a_few_inserts() {
+    let (dir, prefix) = common::store("builder_tiny");
+    let mut tiny: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(200).compaction_trigger(100).build().unwrap();
+    for i in 0..50u64 {
+        tiny.insert(i, "x".into()).unwrap();
+    }
+    assert!(tiny.stats().unwrap().flushes >= 2);
+    assert!(common::files(&dir, ".data").len() >= 2);
+    for i in 0..50u64 {
+        assert_eq!(tiny.get(i).unwrap(), Some("x".into()));
+    }
+
+    // With the default size the same inserts stay in memory.
+    let (dir, prefix) = common::store("builder_default");
+    let mut default: DingoStore = DingoStoreBuilder::new(&prefix).build().unwrap();
+    for i in 0..50u64 {
+        default.insert(i, "x".into()).unwrap();
+    }
+    assert_eq!(default.stats().unwrap().flushes, 0);
+    assert!(common::files(&dir, ".data").is_empty());
+}
+
+#[test]
+fn compaction_trigger_bounds_the_table_count() {
+    let (_dir, prefix) = common::store("builder_trigger");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(200).compaction_trigger(3).build().unwrap();
+    for i in 0..200u64 {
+        ds.insert(i, "x".into()).unwrap();
+        assert!(ds.stats().unwrap().sstables <= 4);
+    }
+    assert!(ds.stats().unwrap().compactions > 0);
+    assert_eq!(ds.range(..).unwrap().count(), 200);
+}
+
+#[test]
+fn data_dir_holds_every_file() {
+    let (dir, _prefix) = common::store("builder_data_dir");
+    let data_dir = dir.join("nested").join("data");
+    let build = || DingoStoreBuilder::new("data_dir_store").data_dir(&data_dir).memtable_size_bytes(200).compaction_trigger(100);
+    {
+        let mut ds: DingoStore = build().build().unwrap();
+        for i in 0..50u64 {
+            ds.insert(i, format!("v{}", i)).unwrap();
+        }
+        ds.flush().unwrap();
+    }
+    let tables = common::files(&data_dir, ".data");
+    assert!(tables.len() >= 2);
+    assert!(tables.iter().all(|table| table.file_name().unwrap().to_string_lossy().starts_with("data_dir_store_")));
+    assert!(data_dir.join("data_dir_store.wal").exists());
+    assert!(data_dir.join("data_dir_store.manifest").exists());
+    // Nothing lands next to the data directory or in the current one.
+    for elsewhere in [dir.clone(), std::env::current_dir().unwrap()] {
+        let names: Vec<String> = std::fs::read_dir(&elsewhere)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("data_dir_store"))
+            .collect();
+        assert!(names.is_empty(), "{:?} in {}", names, elsewhere.display());
+    }
+
+    let ds: DingoStore = build().open().unwrap();
+    assert_eq!(ds.range(..).unwrap().count(), 50);
+}
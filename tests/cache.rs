@@ -0,0 +1,96 @@
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use dingodb::dingostore::{add_integers, DingoStore, DingoStoreBuilder, Durability};
+
+#[test]
+fn cached_lookups_skip_the_table() {
+    let (dir, prefix) = common::store("cache_hit");
+    {
+        let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        for i in 0..10u64 {
+            ds.insert(i, format!("v{}", i)).unwrap();
+        }
+    }
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).cache_capacity(2).open().unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("v1".into()));
+    assert_eq!(ds.get(2).unwrap(), Some("v2".into()));
+    assert_eq!(ds.get(42).unwrap(), None);
+    // Only lookups served from the cache can still succeed.
+    for table in common::files(&dir, ".data") {
+        std::fs::remove_file(table).unwrap();
+    }
+    assert_eq!(ds.get(2).unwrap(), Some("v2".into()));
+    assert_eq!(ds.get(42).unwrap(), None);
+    assert!(ds.get(1).is_err(), "evicted by the two lookups since");
+    ds.insert(2, "new".into()).unwrap();
+    assert_eq!(ds.get(2).unwrap(), Some("new".into()));
+}
+
+#[test]
+fn insert_returning_old_leaves_the_cache_alone() {
+    let (dir, prefix) = common::store("cache_returning_old");
+    {
+        let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        for i in 0..10u64 {
+            ds.insert(i, format!("v{}", i)).unwrap();
+        }
+    }
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).cache_capacity(2).open().unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("v1".into()));
+    assert_eq!(ds.get(2).unwrap(), Some("v2".into()));
+    // Read from the table without evicting either of the two cached keys, or counting as a get.
+    assert_eq!(ds.insert_returning_old(3, "new".into()).unwrap(), Some("v3".into()));
+    assert_eq!(ds.stats().unwrap().gets, 2);
+    for table in common::files(&dir, ".data") {
+        std::fs::remove_file(table).unwrap();
+    }
+    assert_eq!(ds.get(1).unwrap(), Some("v1".into()));
+    assert_eq!(ds.get(2).unwrap(), Some("v2".into()));
+    assert_eq!(ds.get(3).unwrap(), Some("new".into()));
+}
+
+#[test]
+fn cached_lookups_never_outlive_a_write() {
+    let (_dir, prefix) = common::store("cache_race");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).cache_capacity(64).durability(Durability::NoSync).build().unwrap();
+    ds.insert(1, "0".into()).unwrap();
+    ds.flush().unwrap();
+    let done = Arc::new(AtomicBool::new(false));
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let (ds, done) = (ds.clone(), Arc::clone(&done));
+            std::thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    ds.get(1).unwrap();
+                }
+            })
+        })
+        .collect();
+    for i in 1..200u64 {
+        ds.insert(1, i.to_string()).unwrap();
+        ds.flush().unwrap();
+        assert_eq!(ds.get(1).unwrap(), Some(i.to_string()));
+    }
+    done.store(true, Ordering::Relaxed);
+    for reader in readers {
+        reader.join().unwrap();
+    }
+}
+
+#[test]
+fn merges_pending_in_memory_are_not_cached() {
+    let (_dir, prefix) = common::store("cache_merge");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).cache_capacity(16).merge_operator(add_integers).build().unwrap();
+    ds.merge(31, "1".into()).unwrap();
+    assert_eq!(ds.get(31).unwrap(), Some("1".into()));
+    assert_eq!(ds.get_many(&[31]).unwrap(), vec![Some("1".into())]);
+    ds.flush().unwrap();
+    assert_eq!(ds.get(31).unwrap(), Some("1".into()));
+    assert_eq!(ds.get_many(&[31]).unwrap(), vec![Some("1".into())]);
+    ds.merge(31, "2".into()).unwrap();
+    ds.flush().unwrap();
+    assert_eq!(ds.get(31).unwrap(), Some("3".into()));
+}
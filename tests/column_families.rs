@@ -0,0 +1,31 @@
+mod common;
+
+use dingodb::dingostore::{DingoError, DingoStore};
+
+#[test]
+fn families_keep_their_keys_apart() {
+    let (dir, prefix) = common::store("column_families");
+    {
+        let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        let mut users = ds.cf("users").unwrap();
+        let mut sessions = ds.cf("sessions").unwrap();
+        users.insert(7, "alice".into()).unwrap();
+        sessions.insert(7, "token".into()).unwrap();
+        users.insert(8, "bob".into()).unwrap();
+        users.flush().unwrap();
+        assert_eq!(users.get(7).unwrap(), Some("alice".into()));
+        assert_eq!(sessions.get(7).unwrap(), Some("token".into()));
+        assert_eq!(sessions.get(8).unwrap(), None);
+        assert_eq!(ds.get(7).unwrap(), None);
+        assert!(matches!(ds.cf("no/slashes"), Err(DingoError::InvalidFamilyName(_))));
+    }
+    assert!(common::files(&dir, ".data").iter().any(|table| table.to_string_lossy().contains("db_users_")));
+
+    // Both come back from the shared WAL and manifest.
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.cf("users").unwrap().get(7).unwrap(), Some("alice".into()));
+    assert_eq!(ds.cf("users").unwrap().get(8).unwrap(), Some("bob".into()));
+    assert_eq!(ds.cf("sessions").unwrap().get(7).unwrap(), Some("token".into()));
+    assert_eq!(ds.cf("sessions").unwrap().len().unwrap(), 1);
+    assert_eq!(ds.get(7).unwrap(), None);
+}
@@ -0,0 +1,24 @@
+// Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+// A fresh, empty directory for one test's store, and the store's file prefix inside it.
+pub fn store(name: &str) -> (PathBuf, String) {
+    let dir = std::env::temp_dir().join("dingostore-tests").join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let prefix = dir.join("db").to_string_lossy().into_owned();
+    (dir, prefix)
+}
+
+// The files in `dir` whose names end in `suffix`, sorted.
+pub fn files(dir: &PathBuf, suffix: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().ends_with(suffix))
+        .collect();
+    files.sort();
+    files
+}
@@ -0,0 +1,176 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use dingodb::dingostore::{CompactionStyle, DingoStore, DingoStoreBuilder, Durability};
+
+#[test]
+fn compaction_keeps_every_key() {
+    let (dir, prefix) = common::store("compaction_size_tiered");
+    let build = || DingoStoreBuilder::new(&prefix).memtable_size_bytes(2000).compaction_trigger(100);
+    let check = |ds: &DingoStore| {
+        for i in 0..1000u64 {
+            assert_eq!(ds.get(i).unwrap(), Some(format!("v{}", i + 2000)), "key {}", i);
+        }
+    };
+    let mut ds: DingoStore = build().build().unwrap();
+    for i in 0..3000u64 {
+        ds.insert(i % 1000, format!("v{}", i)).unwrap();
+    }
+    ds.flush().unwrap();
+    assert!(ds.stats().unwrap().flushes > 10);
+    assert!(common::files(&dir, ".data").len() > 10);
+    check(&ds);
+
+    ds.compact_now().unwrap();
+    assert_eq!(ds.stats().unwrap().sstables, 1);
+    assert_eq!(common::files(&dir, ".data").len(), 1);
+    check(&ds);
+    drop(ds);
+    let ds: DingoStore = build().open().unwrap();
+    check(&ds);
+}
+
+#[test]
+fn leveled_compaction_keeps_levels_free_of_overlaps() {
+    let (_dir, prefix) = common::store("compaction_leveled");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix)
+        .memtable_size_bytes(2000)
+        .compaction_trigger(4)
+        .compaction_style(CompactionStyle::Leveled)
+        .durability(Durability::NoSync)
+        .build()
+        .unwrap();
+    // Keys scattered over 0..5000 so every flushed table overlaps the levels below it.
+    for i in 0..15_000u64 {
+        ds.insert(i * 7919 % 5000, format!("v{}", i)).unwrap();
+    }
+    ds.flush().unwrap();
+    assert!(ds.stats().unwrap().compactions > 1);
+
+    let levels = ds.levels().unwrap();
+    assert!(levels.keys().filter(|&&level| level >= 1).count() >= 2, "levels {:?}", levels.keys());
+    for (level, ranges) in levels.iter().filter(|(&level, _)| level >= 1) {
+        let mut ranges = ranges.clone();
+        ranges.sort();
+        for pair in ranges.windows(2) {
+            assert!(pair[0].1 < pair[1].0, "level {} has {:?} and {:?}", level, pair[0], pair[1]);
+        }
+    }
+    for i in 10_000..15_000u64 {
+        assert_eq!(ds.get(i * 7919 % 5000).unwrap(), Some(format!("v{}", i)));
+    }
+    // The manifest keeps each table's level.
+    drop(ds);
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.levels().unwrap(), levels);
+}
+
+// Four tables over the same keys, the last of them deleting key 3.
+fn overlapping_tables(ds: &mut DingoStore) {
+    for round in 0..4u64 {
+        for i in 0..50u64 {
+            ds.insert(i, format!("r{}-{}", round, i)).unwrap();
+        }
+        if round == 3 {
+            ds.delete(3).unwrap();
+        }
+        ds.flush().unwrap();
+    }
+}
+
+#[test]
+fn compact_now_merges_overlapping_tables_into_one() {
+    let (dir, prefix) = common::store("compaction_now");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).compaction_trigger(100).build().unwrap();
+    overlapping_tables(&mut ds);
+    assert_eq!(ds.stats().unwrap().sstables, 4);
+    ds.compact_now().unwrap();
+    assert_eq!(ds.stats().unwrap().sstables, 1);
+    assert_eq!(common::files(&dir, ".data").len(), 1);
+    for i in 0..50u64 {
+        assert_eq!(ds.get(i).unwrap(), if i == 3 { None } else { Some(format!("r3-{}", i)) });
+    }
+    assert_eq!(ds.len().unwrap(), 49);
+}
+
+#[test]
+fn compaction_interval_merges_tables_without_writes() {
+    let (dir, prefix) = common::store("compaction_interval");
+    let mut ds: DingoStore =
+        DingoStoreBuilder::new(&prefix).compaction_trigger(100).compaction_interval(Duration::from_millis(20)).build().unwrap();
+    overlapping_tables(&mut ds);
+    let started = Instant::now();
+    while ds.stats().unwrap().sstables > 1 {
+        assert!(started.elapsed() < Duration::from_secs(10), "no compaction ran");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(ds.get(3).unwrap(), None);
+    assert_eq!(ds.get(4).unwrap(), Some("r3-4".into()));
+    drop(ds);
+    assert_eq!(common::files(&dir, ".data").len(), 1);
+}
+
+#[test]
+fn dropping_the_store_stops_its_compaction_timer() {
+    let (_dir, prefix) = common::store("compaction_interval_drop");
+    let mut ds: DingoStore =
+        DingoStoreBuilder::new(&prefix).compaction_interval(Duration::from_secs(3600)).build().unwrap();
+    ds.insert(1, "one".into()).unwrap();
+    let started = Instant::now();
+    drop(ds);
+    assert!(started.elapsed() < Duration::from_secs(60));
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("one".into()));
+}
+
+#[test]
+fn small_runs_merge_only_the_small_tables() {
+    let (dir, prefix) = common::store("compaction_small_runs");
+    let big = || DingoStoreBuilder::new(&prefix).memtable_size_bytes(1_000_000).compaction_trigger(100);
+    let small = || {
+        DingoStoreBuilder::new(&prefix).memtable_size_bytes(20_000).compaction_trigger(5).compaction_style(CompactionStyle::SmallRuns)
+    };
+    let mut want = BTreeMap::new();
+    let mut write = |builder: DingoStoreBuilder, keys: &mut dyn Iterator<Item = u64>, tag: &str, every: u64| {
+        let mut ds: DingoStore = builder.open().unwrap();
+        let mut tables = Vec::new();
+        for (n, key) in keys.enumerate() {
+            ds.insert(key, format!("{}{}", tag, key)).unwrap();
+            want.insert(key, format!("{}{}", tag, key));
+            if n as u64 % every == every - 1 {
+                tables.push(ds.flush().unwrap().unwrap());
+            }
+        }
+        tables
+    };
+    let mut big_tables = write(big(), &mut (0..20_000), "a", 20_000);
+    let mut small_tables = write(small(), &mut (0..12).map(|i| i * 7), "b", 4);
+    big_tables.extend(write(big(), &mut (5000..25_000), "c", 20_000));
+    small_tables.extend(write(small(), &mut (0..8).map(|i| i * 11), "d", 4));
+    assert_eq!(common::files(&dir, ".data").len(), 7);
+
+    // Compaction runs on the flush thread once a write fills the memtable and flushes it, which
+    // also takes the store past the trigger.
+    let mut ds: DingoStore = small().open().unwrap();
+    for i in 0..1000u64 {
+        ds.insert(50_000 + i, format!("e{}", i)).unwrap();
+        want.insert(50_000 + i, format!("e{}", i));
+    }
+    let started = Instant::now();
+    while ds.stats().unwrap().compactions == 0 {
+        assert!(started.elapsed() < Duration::from_secs(10), "no compaction ran");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let stats = ds.stats().unwrap();
+    assert_eq!(stats.flushes, 1, "{:?}", stats);
+    assert!(stats.compactions > 0, "{:?}", stats);
+    assert!(stats.sstables < 8, "{:?}", stats);
+    assert!(stats.sstables > big_tables.len(), "{:?}", stats);
+    for table in &big_tables {
+        assert!(std::path::Path::new(table).exists(), "{} was rewritten", table);
+    }
+    assert!(small_tables.iter().any(|table| !std::path::Path::new(table).exists()));
+    assert_eq!(ds.range(..).unwrap().map(Result::unwrap).collect::<BTreeMap<_, _>>(), want);
+}
@@ -0,0 +1,25 @@
+mod common;
+
+use dingodb::dingostore::{Compression, DingoStore, DingoStoreBuilder};
+
+#[test]
+fn compressed_tables_are_smaller_and_read_back() {
+    let value = |i: u64| format!("{}-{}", i, "the quick brown fox jumps over the lazy dog ".repeat(20));
+    let mut sizes = Vec::new();
+    for (name, compression) in [("compression_lz4", Compression::Lz4), ("compression_none", Compression::None)] {
+        let (dir, prefix) = common::store(name);
+        {
+            let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).compression(compression).build().unwrap();
+            for i in 0..500u64 {
+                ds.insert(i, value(i)).unwrap();
+            }
+        }
+        let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        for i in 0..500u64 {
+            assert_eq!(ds.get(i).unwrap(), Some(value(i)));
+        }
+        assert_eq!(ds.range(100..200).unwrap().count(), 100);
+        sizes.push(common::files(&dir, ".data").iter().map(|table| std::fs::metadata(table).unwrap().len()).sum::<u64>());
+    }
+    assert!(sizes[0] * 4 < sizes[1], "{} bytes compressed, {} not", sizes[0], sizes[1]);
+}
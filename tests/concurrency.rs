@@ -0,0 +1,165 @@
+mod common;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use dingodb::dingostore::{DingoStore, DingoStoreBuilder, Durability};
+
+#[test]
+fn readers_and_a_writer_share_a_store() {
+    let (_dir, prefix) = common::store("concurrency_threads");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(8000).durability(Durability::NoSync).build().unwrap();
+    let done = Arc::new(AtomicBool::new(false));
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let ds = ds.clone();
+            let done = Arc::clone(&done);
+            std::thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    // Keys are written in order, each value naming its key.
+                    let mut prev = None;
+                    for item in ds.range(..).unwrap() {
+                        let (key, value) = item.unwrap();
+                        assert_eq!(value, format!("v{}", key));
+                        assert!(prev.is_none_or(|prev| prev < key));
+                        prev = Some(key);
+                    }
+                    if let Some(last) = prev {
+                        assert_eq!(ds.get(last).unwrap(), Some(format!("v{}", last)));
+                    }
+                }
+            })
+        })
+        .collect();
+    for i in 0..5000u64 {
+        ds.insert(i, format!("v{}", i)).unwrap();
+    }
+    done.store(true, Ordering::SeqCst);
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    assert!(ds.stats().unwrap().flushes > 0);
+    assert_eq!(ds.range(..).unwrap().count(), 5000);
+}
+
+#[tokio::test]
+async fn async_calls_go_through_the_handle() {
+    let (_dir, prefix) = common::store("concurrency_async");
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    let writes: Vec<_> = (0..20u64)
+        .map(|i| {
+            let ds = ds.clone();
+            tokio::spawn(async move { ds.insert_async(i, format!("v{}", i)).await })
+        })
+        .collect();
+    for write in writes {
+        write.await.unwrap().unwrap();
+    }
+    for i in 0..20u64 {
+        assert_eq!(ds.get_async(i).await.unwrap(), Some(format!("v{}", i)));
+    }
+    assert_eq!(ds.get_async(20).await.unwrap(), None);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_async_reads_and_writes() {
+    let (_dir, prefix) = common::store("concurrency_async_tasks");
+    let ds: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(2000).build().unwrap();
+    let tasks: Vec<_> = (0..8u64)
+        .map(|task| {
+            let ds = ds.clone();
+            tokio::spawn(async move {
+                for i in 0..100u64 {
+                    let key = task * 1000 + i;
+                    ds.insert_async(key, format!("v{}", key)).await.unwrap();
+                    assert_eq!(ds.get_async(key).await.unwrap(), Some(format!("v{}", key)));
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert!(ds.stats().unwrap().flushes > 0);
+    for key in (0..8u64).flat_map(|task| (0..100).map(move |i| task * 1000 + i)) {
+        assert_eq!(ds.get_async(key).await.unwrap(), Some(format!("v{}", key)));
+    }
+}
+
+#[test]
+fn clones_are_handles_to_one_store() {
+    let (dir, prefix) = common::store("concurrency_clones");
+    let mut a: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(2000).build().unwrap();
+    let mut b = a.clone();
+    a.insert(1, "from a".into()).unwrap();
+    assert_eq!(b.get(1).unwrap(), Some("from a".into()));
+    b.insert(2, "from b".into()).unwrap();
+    assert_eq!(a.get(2).unwrap(), Some("from b".into()));
+    for i in 10..500u64 {
+        b.insert(i, format!("v{}", i)).unwrap();
+    }
+    assert_eq!(a.get(10).unwrap(), Some("v10".into()));
+    assert_eq!(a.stats().unwrap(), b.stats().unwrap());
+
+    // The store stays open until its last handle is dropped, which flushes it.
+    drop(a);
+    b.insert(3, "still open".into()).unwrap();
+    let c = b.clone();
+    drop(b);
+    assert_eq!(c.get(3).unwrap(), Some("still open".into()));
+    assert_eq!(common::files(&dir, ".wal").len(), 1);
+    drop(c);
+    assert_eq!(std::fs::metadata(format!("{}.wal", prefix)).unwrap().len(), 0);
+    let d: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(d.get(3).unwrap(), Some("still open".into()));
+    assert_eq!(d.get(1).unwrap(), Some("from a".into()));
+}
+
+// Readers keep reading the keys written last while flushes move them out of the memtable, and
+// never find one missing.
+#[test]
+fn keys_being_flushed_stay_readable() {
+    let (_dir, prefix) = common::store("concurrency_flush_gap");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix)
+        .memtable_size_bytes(4000)
+        .compaction_trigger(4)
+        .durability(Durability::NoSync)
+        .build()
+        .unwrap();
+    // How many keys have been written so far, or u64::MAX once the writer is done.
+    let written = Arc::new(AtomicU64::new(0));
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let ds = ds.clone();
+            let written = Arc::clone(&written);
+            std::thread::spawn(move || {
+                let (mut misses, mut reads) = (0, 0);
+                loop {
+                    let hi = written.load(Ordering::SeqCst);
+                    if hi == u64::MAX {
+                        return (misses, reads);
+                    }
+                    for key in hi.saturating_sub(30)..hi {
+                        reads += 1;
+                        if ds.get(key).unwrap() != Some(format!("v{}", key)) {
+                            misses += 1;
+                        }
+                    }
+                    let keys: Vec<u64> = (hi.saturating_sub(20)..hi).collect();
+                    misses += ds.get_many(&keys).unwrap().iter().filter(|value| value.is_none()).count();
+                }
+            })
+        })
+        .collect();
+    for i in 0..10_000u64 {
+        ds.insert(i, format!("v{}", i)).unwrap();
+        written.store(i + 1, Ordering::SeqCst);
+    }
+    written.store(u64::MAX, Ordering::SeqCst);
+    for reader in readers {
+        let (misses, reads) = reader.join().unwrap();
+        assert_eq!(misses, 0);
+        assert!(reads > 1000);
+    }
+    assert!(ds.stats().unwrap().flushes > 20);
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use dingodb::dingostore::{DingoStore, DingoStoreBuilder, Durability};
+
+// Writes 1000 keys through several flushes, then drops the store.
+fn write_through_flushes(prefix: &str, durability: Durability) {
+    let mut ds: DingoStore = DingoStoreBuilder::new(prefix).memtable_size_bytes(2000).durability(durability).build().unwrap();
+    for i in 0..1000u64 {
+        ds.insert(i, format!("v{}", i)).unwrap();
+    }
+    assert!(ds.stats().unwrap().flushes > 1);
+}
+
+#[test]
+fn sync_on_flush_persists_across_a_reopen() {
+    let (_dir, prefix) = common::store("durability_sync_on_flush");
+    write_through_flushes(&prefix, Durability::SyncOnFlush);
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    for i in 0..1000u64 {
+        assert_eq!(ds.get(i).unwrap(), Some(format!("v{}", i)));
+    }
+}
+
+// NoSync leaves syncing to the OS, so only a machine crash can lose what it wrote, and the tail
+// of the WAL or the newest tables may then be gone. Reopening after the process exits cleanly
+// reads everything back, as the writes all reached the page cache.
+#[test]
+fn no_sync_reads_back_after_a_clean_exit() {
+    let (_dir, prefix) = common::store("durability_no_sync");
+    write_through_flushes(&prefix, Durability::NoSync);
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    for i in 0..1000u64 {
+        assert_eq!(ds.get(i).unwrap(), Some(format!("v{}", i)));
+    }
+}
+
+#[test]
+fn sync_every_write_logs_before_returning() {
+    let (_dir, prefix) = common::store("durability_every_write");
+    {
+        let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).durability(Durability::SyncEveryWrite).build().unwrap();
+        for i in 0..10u64 {
+            ds.insert(i, format!("v{}", i)).unwrap();
+        }
+        std::mem::forget(ds);
+    }
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.range(..).unwrap().count(), 10);
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use dingodb::dingostore::{DingoStore, DingoStoreBuilder};
+
+#[test]
+fn absent_keys_skip_tables_through_their_filters() {
+    let (_dir, prefix) = common::store("filters_bloom");
+    {
+        let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(100_000).compaction_trigger(100).build().unwrap();
+        // Scattered, so every table spans the whole key range.
+        for i in (0..20_000u64).map(|i| i * 7919 % 20_000) {
+            ds.insert(i * 2, format!("v{}", i)).unwrap();
+        }
+    }
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    let tables = ds.stats().unwrap().sstables as u64;
+    assert!(tables > 3);
+    for i in 0..20_000u64 {
+        assert_eq!(ds.get(i * 2 + 1).unwrap(), None);
+    }
+    let stats = ds.stats().unwrap();
+    // Each absent key checks the filter of nearly every table; only the false positives go on to
+    // read one.
+    assert!(stats.bloom_hits + stats.bloom_misses > 19_000 * tables, "{:?}", stats);
+    assert!(stats.bloom_hits * 50 < stats.bloom_misses, "{:?}", stats);
+
+    for i in 0..20_000u64 {
+        assert_eq!(ds.get(i * 2).unwrap(), Some(format!("v{}", i)));
+    }
+}
+
+#[test]
+fn keys_outside_every_table_touch_no_file() {
+    let (_dir, prefix) = common::store("filters_ranges");
+    {
+        let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).compaction_trigger(100).build().unwrap();
+        for range in [0..100u64, 200..300, 400..500] {
+            for i in range {
+                ds.insert(i, format!("v{}", i)).unwrap();
+            }
+            ds.flush().unwrap();
+        }
+    }
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.stats().unwrap().sstables, 3);
+    // Past the last key of the table whose first key is the largest one below them.
+    for key in [100, 150, 199, 350, 500, 10_000] {
+        assert_eq!(ds.get(key).unwrap(), None);
+    }
+    let stats = ds.stats().unwrap();
+    assert_eq!((stats.bloom_hits + stats.bloom_misses, stats.footer_reads), (0, 0), "{:?}", stats);
+
+    // A key inside one table's range checks that table alone.
+    assert_eq!(ds.get(250).unwrap(), Some("v250".into()));
+    let stats = ds.stats().unwrap();
+    assert_eq!(stats.bloom_hits + stats.bloom_misses, 1, "{:?}", stats);
+}
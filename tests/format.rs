@@ -0,0 +1,154 @@
+mod common;
+
+use dingodb::dingostore::{add_integers, DingoError, DingoStore, DingoStoreBuilder};
+
+// A store with one SSTable holding a value, a tombstone, an expiring value and a merge operand.
+fn table_of_every_kind(prefix: &str, block_size: u32) -> String {
+    let mut ds: DingoStore = DingoStoreBuilder::new(prefix).block_size(block_size).merge_operator(add_integers).build().unwrap();
+    for i in 0..300u64 {
+        ds.insert(i, format!("v{}", i)).unwrap();
+    }
+    ds.insert_with_ttl(1000, "ttl".into(), std::time::Duration::from_secs(3600)).unwrap();
+    ds.delete(5).unwrap();
+    ds.merge(2000, "3".into()).unwrap();
+    ds.flush().unwrap().unwrap()
+}
+
+// The block size in a table's footer, and the offset of every block its index lists. The footer
+// ends with the block size, where the index starts, the index's length in entries, the filter's
+// length and hash count, and the magic; each index entry is a u64 key and an offset.
+fn block_offsets(table: &str) -> (u64, Vec<u64>) {
+    let bytes = std::fs::read(table).unwrap();
+    let u32_at = |at: usize| u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap()) as u64;
+    let u64_at = |at: usize| u64::from_be_bytes(bytes[at..at + 8].try_into().unwrap());
+    let footer = bytes.len() - 32;
+    let index = u64_at(footer + 4) as usize;
+    let offsets = (0..u32_at(footer + 12) as usize).map(|entry| u64_at(index + entry * 16 + 8)).collect();
+    (u32_at(footer), offsets)
+}
+
+#[test]
+fn blocks_start_on_block_boundaries() {
+    let (dir, prefix) = common::store("format_blocks");
+    {
+        let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(200_000).block_size(512).build().unwrap();
+        for i in 0..3000u64 {
+            ds.insert(i, format!("v{}", i)).unwrap();
+        }
+        // Bigger than a block.
+        ds.insert(5000, "x".repeat(2000)).unwrap();
+        ds.insert(5001, "after".into()).unwrap();
+        ds.delete(7).unwrap();
+    }
+    let tables = common::files(&dir, ".data");
+    assert!(!tables.is_empty());
+    for table in &tables {
+        let (block_size, offsets) = block_offsets(&table.to_string_lossy());
+        assert_eq!(block_size, 512);
+        assert!(offsets.len() > 10);
+        assert!(offsets.iter().all(|offset| offset % 512 == 0), "{:?}", offsets);
+    }
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    for i in 0..3000u64 {
+        let want = if i == 7 { None } else { Some(format!("v{}", i)) };
+        assert_eq!(ds.get(i).unwrap(), want);
+    }
+    assert_eq!(ds.get(5000).unwrap(), Some("x".repeat(2000)));
+    assert_eq!(ds.get(5001).unwrap(), Some("after".into()));
+    assert_eq!(ds.range(2990..).unwrap().count(), 12);
+}
+
+#[test]
+fn a_corrupted_block_is_reported() {
+    let (_dir, prefix) = common::store("format_corruption");
+    let table = table_of_every_kind(&prefix, 512);
+    let mut bytes = std::fs::read(&table).unwrap();
+    // Inside the first record of the first block.
+    bytes[512 + 20] ^= 0xff;
+    std::fs::write(&table, &bytes).unwrap();
+
+    let ds: DingoStore = DingoStoreBuilder::new(&prefix).merge_operator(add_integers).open().unwrap();
+    match ds.get(0) {
+        Err(DingoError::Corruption { file, offset }) => {
+            assert_eq!(file, table);
+            assert_eq!(offset, 516);
+        }
+        other => panic!("expected a corruption error, got {:?}", other),
+    }
+    let first = ds.range(..).map(|mut items| items.next());
+    assert!(matches!(first, Err(DingoError::Corruption { .. }) | Ok(Some(Err(DingoError::Corruption { .. })))));
+    // Other blocks still read back.
+    assert_eq!(ds.get(299).unwrap(), Some("v299".into()));
+}
+
+#[test]
+fn tables_from_a_newer_version_are_refused() {
+    for block_size in [0, 512] {
+        let (_dir, prefix) = common::store(&format!("format_version_{}", block_size));
+        let table = table_of_every_kind(&prefix, block_size);
+        let bytes = std::fs::read(&table).unwrap();
+        assert_eq!(&bytes[..5], &[0xD1, 0xE6, 0x05, 0xC0, 1]);
+        {
+            let ds: DingoStore = DingoStoreBuilder::new(&prefix).merge_operator(add_integers).open().unwrap();
+            assert_eq!(ds.get(7).unwrap(), Some("v7".into()));
+            assert_eq!(ds.get(5).unwrap(), None);
+            assert_eq!(ds.get(1000).unwrap(), Some("ttl".into()));
+            assert_eq!(ds.get(2000).unwrap(), Some("3".into()));
+            assert_eq!(ds.range(..).unwrap().count(), 301);
+        }
+
+        let mut bumped = bytes;
+        bumped[4] = 2;
+        std::fs::write(&table, &bumped).unwrap();
+        let ds: DingoStore = DingoStoreBuilder::new(&prefix).merge_operator(add_integers).open().unwrap();
+        match ds.get(7) {
+            Err(DingoError::UnsupportedVersion { file, version }) => {
+                assert_eq!(file, table);
+                assert_eq!(version, 2);
+            }
+            other => panic!("expected a version error, got {:?}", other),
+        }
+        assert!(ds.get(7).unwrap_err().to_string().contains("unsupported format version 2"));
+    }
+}
+
+#[test]
+fn first_block_starts_on_a_block_boundary() {
+    let (_dir, prefix) = common::store("format_first_block");
+    let table = table_of_every_kind(&prefix, 512);
+    let bytes = std::fs::read(&table).unwrap();
+    assert!(bytes[5..512].iter().all(|b| *b == 0));
+    assert_ne!(u32::from_be_bytes(bytes[512..516].try_into().unwrap()), 0);
+}
+
+#[test]
+fn range_tombstones_open_their_table() {
+    for block_size in [0, 512] {
+        let (_dir, prefix) = common::store(&format!("format_range_tombstones_{}", block_size));
+        let table = {
+            let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).block_size(block_size).compaction_trigger(100).build().unwrap();
+            for i in 0..300u64 {
+                ds.insert(i, format!("v{}", i)).unwrap();
+            }
+            ds.flush().unwrap();
+            ds.delete_range(100..200).unwrap();
+            ds.insert(150, "new".into()).unwrap();
+            ds.flush().unwrap().unwrap()
+        };
+        // Tagged 4, and keyed by the start of the range, ahead of the table's one record.
+        let bytes = std::fs::read(&table).unwrap();
+        let first = if block_size == 0 { 5 } else { 512 + 4 };
+        assert_eq!(bytes[first], 4);
+        assert_eq!(&bytes[first + 1..first + 9], &100u64.to_be_bytes());
+
+        let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        assert_eq!(ds.get(120).unwrap(), None);
+        assert_eq!(ds.get(150).unwrap(), Some("new".into()));
+        assert_eq!(ds.get(250).unwrap(), Some("v250".into()));
+        let keys: Vec<u64> = ds.range(..).unwrap().map(|item| item.unwrap().0).collect();
+        assert_eq!(keys, (0..100).chain([150]).chain(200..300).collect::<Vec<u64>>());
+        let mut rev: Vec<u64> = ds.range_rev(..).unwrap().map(|item| item.unwrap().0).collect();
+        rev.reverse();
+        assert_eq!(rev, keys);
+    }
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use dingodb::dingostore::{DingoStore, DingoStoreBuilder};
+
+#[test]
+fn an_export_imports_into_an_identical_store() {
+    let (_dir, prefix) = common::store("json_export");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(2000).build().unwrap();
+    for i in 0..300u64 {
+        ds.insert(i % 200, format!("v{} \"quoted\"\n", i)).unwrap();
+    }
+    ds.delete(7).unwrap();
+    assert!(ds.stats().unwrap().flushes > 1);
+    let mut export = Vec::new();
+    ds.export_json(&mut export).unwrap();
+    let lines: Vec<&str> = std::str::from_utf8(&export).unwrap().lines().collect();
+    assert_eq!(lines.len(), 199);
+    assert_eq!(lines[0], r#"{"key":0,"value":"v200 \"quoted\"\n"}"#);
+
+    let (_dir, prefix) = common::store("json_import");
+    let mut copy: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(2000).build().unwrap();
+    copy.import_json(export.as_slice()).unwrap();
+    assert!(copy.stats().unwrap().flushes > 0);
+    let pairs = |ds: &DingoStore| ds.range(..).unwrap().map(Result::unwrap).collect::<Vec<_>>();
+    assert_eq!(pairs(&copy), pairs(&ds));
+    assert_eq!(copy.get(7).unwrap(), None);
+
+    // Records before a malformed one stay imported.
+    let (_dir, prefix) = common::store("json_malformed");
+    let mut partial: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert!(partial.import_json(&b"{\"key\":1,\"value\":\"one\"}\n{\"key\":"[..]).is_err());
+    assert_eq!(partial.get(1).unwrap(), Some("one".into()));
+}
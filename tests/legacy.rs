@@ -0,0 +1,75 @@
+mod common;
+
+use dingodb::dingostore::{DingoError, DingoStore};
+
+// An SSTable as the first version of the store wrote it: no footer, and each record a u64 key,
+// the value's length (u32) and the value's bare UTF-8 bytes.
+fn baseline_table(pairs: &[(u64, &[u8])]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (key, val) in pairs {
+        bytes.extend_from_slice(&key.to_be_bytes());
+        bytes.extend_from_slice(&(val.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(val);
+    }
+    bytes
+}
+
+#[test]
+fn baseline_tables_read_back_as_strings() {
+    let (dir, prefix) = common::store("legacy_baseline");
+    std::fs::write(dir.join("db_1000.data"), baseline_table(&[(1, b"one"), (2, b""), (5, b"five")])).unwrap();
+    std::fs::write(dir.join("db_1001.data"), baseline_table(&[(2, b"two")])).unwrap();
+    // A crash mid-flush left the last record half written.
+    let mut torn = baseline_table(&[(7, b"seven"), (8, b"eight")]);
+    torn.truncate(torn.len() - 3);
+    std::fs::write(dir.join("db_1002.data"), torn).unwrap();
+    let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("one".into()));
+    assert_eq!(ds.get(2).unwrap(), Some("two".into()));
+    assert_eq!(ds.get(5).unwrap(), Some("five".into()));
+    assert_eq!(ds.get(3).unwrap(), None);
+    assert_eq!(ds.get(7).unwrap(), Some("seven".into()));
+    assert_eq!(ds.get(8).unwrap(), None);
+    let pairs: Vec<(u64, String)> = ds.range(..).unwrap().map(Result::unwrap).collect();
+    assert_eq!(pairs, vec![(1, "one".into()), (2, "two".into()), (5, "five".into()), (7, "seven".into())]);
+    let back: Vec<u64> = ds.range_rev(..).unwrap().map(|pair| pair.unwrap().0).collect();
+    assert_eq!(back, vec![7, 5, 2, 1]);
+
+    // Compaction rewrites them in the current format.
+    ds.delete(5).unwrap();
+    ds.compact_now().unwrap();
+    drop(ds);
+    assert!(!dir.join("db_1000.data").exists());
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("one".into()));
+    assert_eq!(ds.get(2).unwrap(), Some("two".into()));
+    assert_eq!(ds.get(5).unwrap(), None);
+}
+
+#[test]
+fn baseline_tables_read_back_as_bytes() {
+    let (dir, prefix) = common::store("legacy_bytes");
+    std::fs::write(dir.join("db_1000.data"), baseline_table(&[(1, b"one"), (9, &[0xff])])).unwrap();
+    let ds: DingoStore<u64, Vec<u8>> = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some(b"one".to_vec()));
+    assert_eq!(ds.get(9).unwrap(), Some(vec![0xff]));
+    drop(ds);
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert!(matches!(ds.get(9), Err(DingoError::InvalidUtf8 { .. })));
+}
+
+#[test]
+fn a_torn_last_record_leaves_the_others_readable() {
+    let whole = baseline_table(&[(1, b"one"), (2, b"two"), (3, b"three")]);
+    let last = baseline_table(&[(3, b"three")]).len();
+    // Cut inside the key, the length and the value of the last record in turn.
+    for cut in [last - 4, last - 10, last - 13, 1] {
+        let (dir, prefix) = common::store(&format!("legacy_torn_{}", cut));
+        std::fs::write(dir.join("db_1000.data"), &whole[..whole.len() - cut]).unwrap();
+        let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        assert_eq!(ds.get(1).unwrap(), Some("one".into()), "cut {}", cut);
+        assert_eq!(ds.get(2).unwrap(), Some("two".into()), "cut {}", cut);
+        assert_eq!(ds.get(3).unwrap(), None, "cut {}", cut);
+        assert_eq!(ds.keys().unwrap().map(Result::unwrap).collect::<Vec<_>>(), vec![1, 2], "cut {}", cut);
+    }
+}
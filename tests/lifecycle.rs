@@ -0,0 +1,86 @@
+mod common;
+
+use dingodb::dingostore::DingoStore;
+
+#[test]
+fn open_reads_back_what_was_flushed() {
+    let (_dir, prefix) = common::store("lifecycle_open");
+    {
+        let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        for i in 0..3000u64 {
+            ds.insert(i % 1000, format!("v{}", i)).unwrap();
+            if i % 500 == 499 {
+                ds.flush().unwrap();
+            }
+        }
+    }
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert!(ds.stats().unwrap().sstables >= 6);
+    for i in 0..1000u64 {
+        assert_eq!(ds.get(i).unwrap(), Some(format!("v{}", i + 2000)));
+    }
+}
+
+#[test]
+fn drop_flushes_the_memtable() {
+    let (dir, prefix) = common::store("lifecycle_drop");
+    {
+        let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        for i in 0..10u64 {
+            ds.insert(i, format!("v{}", i)).unwrap();
+        }
+        ds.delete(3).unwrap();
+        assert!(common::files(&dir, ".data").is_empty());
+    }
+    assert_eq!(common::files(&dir, ".data").len(), 1);
+    assert_eq!(std::fs::metadata(format!("{}.wal", prefix)).unwrap().len(), 0);
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    for i in 0..10u64 {
+        let want = if i == 3 { None } else { Some(format!("v{}", i)) };
+        assert_eq!(ds.get(i).unwrap(), want);
+    }
+}
+
+#[test]
+fn flush_writes_the_memtable_out_on_demand() {
+    let (dir, prefix) = common::store("lifecycle_flush");
+    {
+        let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        assert_eq!(ds.flush().unwrap(), None);
+        for i in 0..5u64 {
+            ds.insert(i, format!("v{}", i)).unwrap();
+        }
+        let table = ds.flush().unwrap().unwrap();
+        assert_eq!(common::files(&dir, ".data"), vec![std::path::PathBuf::from(&table)]);
+        assert_eq!(ds.stats().unwrap().memtable_bytes, 0);
+        assert_eq!(ds.flush().unwrap(), None);
+        assert_eq!(std::fs::metadata(format!("{}.wal", prefix)).unwrap().len(), 0);
+        // Skips the flush on drop, so what reads back below came from the table.
+        std::mem::forget(ds);
+    }
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.stats().unwrap().sstables, 1);
+    for i in 0..5u64 {
+        assert_eq!(ds.get(i).unwrap(), Some(format!("v{}", i)));
+    }
+}
+
+#[test]
+fn close_leaves_everything_on_disk() {
+    let (dir, prefix) = common::store("lifecycle_close");
+    let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    for i in 0..10u64 {
+        ds.insert(i, format!("v{}", i)).unwrap();
+    }
+    assert!(common::files(&dir, ".data").is_empty());
+    ds.close().unwrap();
+    assert_eq!(common::files(&dir, ".data").len(), 1);
+    assert_eq!(common::files(&dir, ".manifest").len(), 1);
+    assert_eq!(std::fs::metadata(format!("{}.wal", prefix)).unwrap().len(), 0);
+
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.stats().unwrap().memtable_bytes, 0);
+    for i in 0..10u64 {
+        assert_eq!(ds.get(i).unwrap(), Some(format!("v{}", i)));
+    }
+}
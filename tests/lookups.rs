@@ -0,0 +1,101 @@
+mod common;
+
+use std::sync::Mutex;
+
+use dingodb::dingostore::{DingoStore, DingoStoreBuilder};
+
+// Reads are counted process-wide, so the tests in this file take turns.
+static READING: Mutex<()> = Mutex::new(());
+
+// Bytes this process has read from files so far, as Linux counts them.
+fn bytes_read() -> u64 {
+    let io = std::fs::read_to_string("/proc/self/io").unwrap();
+    let line = io.lines().find(|line| line.starts_with("rchar:")).unwrap();
+    line["rchar:".len()..].trim().parse().unwrap()
+}
+
+// A store holding a single SSTable of `records` records, every other key, and the table's size.
+fn big_table(name: &str, records: u64, block_size: u32) -> (String, u64) {
+    let (_dir, prefix) = common::store(name);
+    let table = {
+        let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(u32::MAX).block_size(block_size).build().unwrap();
+        ds.insert_batch((0..records).map(|k| (k * 2, format!("value{:08}", k))).collect()).unwrap();
+        ds.flush().unwrap().unwrap()
+    };
+    (prefix, std::fs::metadata(table).unwrap().len())
+}
+
+#[test]
+fn lookups_read_a_fraction_of_a_big_table() {
+    let _reading = READING.lock().unwrap();
+    let (prefix, table_len) = big_table("lookups_big", 200_000, 4096);
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    // The first lookup loads the footer.
+    assert_eq!(ds.get(0).unwrap(), Some("value00000000".into()));
+
+    let before = bytes_read();
+    for k in (0..200_000u64).step_by(2_003) {
+        assert_eq!(ds.get(k * 2).unwrap(), Some(format!("value{:08}", k)));
+    }
+    let per_lookup = (bytes_read() - before) / 100;
+    assert!(per_lookup * 100 < table_len, "{} bytes per lookup in a {} byte table", per_lookup, table_len);
+}
+
+// Bytes read per lookup of a present key, once the table's footer is loaded.
+fn bytes_per_lookup(prefix: &str, records: u64) -> u64 {
+    let ds: DingoStore = DingoStore::open(prefix).unwrap();
+    assert_eq!(ds.get(0).unwrap(), Some("value00000000".into()));
+    let before = bytes_read();
+    let step = records / 100;
+    for k in (0..records).step_by(step as usize).take(100) {
+        assert_eq!(ds.get(k * 2).unwrap(), Some(format!("value{:08}", k)));
+    }
+    (bytes_read() - before) / 100
+}
+
+#[test]
+fn lookups_without_blocks_grow_with_the_log_of_the_table() {
+    let _reading = READING.lock().unwrap();
+    let (small, small_len) = big_table("lookups_log_small", 10_000, 0);
+    let (large, large_len) = big_table("lookups_log_large", 160_000, 0);
+    assert!(large_len > 15 * small_len);
+    let small_reads = bytes_per_lookup(&small, 10_000);
+    let large_reads = bytes_per_lookup(&large, 160_000);
+    // A scan would read 16 times as much; a binary search only a few more records.
+    assert!(large_reads < 2 * small_reads, "{} bytes per lookup, then {}", small_reads, large_reads);
+    assert!(large_reads * 100 < large_len);
+}
+
+#[test]
+fn preloaded_indexes_spare_the_first_lookups_a_footer_read() {
+    let _reading = READING.lock().unwrap();
+    let (_dir, prefix) = common::store("lookups_preload");
+    {
+        let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).compaction_trigger(100).build().unwrap();
+        for i in 0..2000u64 {
+            ds.insert(i, format!("v{}", i)).unwrap();
+            if i % 100 == 99 {
+                ds.flush().unwrap();
+            }
+        }
+    }
+    let read_all = |ds: &DingoStore| {
+        for i in (0..2000u64).step_by(50) {
+            assert_eq!(ds.get(i).unwrap(), Some(format!("v{}", i)));
+        }
+    };
+
+    let ds: DingoStore = DingoStoreBuilder::new(&prefix).preload_indexes(true).open().unwrap();
+    let stats = ds.stats().unwrap();
+    assert_eq!(stats.sstables, 20);
+    let at_open = stats.footer_reads;
+    assert!(at_open >= 20, "{:?}", stats);
+    read_all(&ds);
+    assert_eq!(ds.stats().unwrap().footer_reads, at_open);
+    drop(ds);
+
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.stats().unwrap().footer_reads, 0);
+    read_all(&ds);
+    assert!(ds.stats().unwrap().footer_reads >= 20);
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use dingodb::dingostore::{DingoError, DingoStore, DingoStoreBuilder};
+
+#[test]
+fn build_over_an_existing_store_keeps_its_tables() {
+    let (_dir, prefix) = common::store("manifest_rebuild");
+    {
+        let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        ds.insert(1, "first run".into()).unwrap();
+        ds.flush().unwrap();
+    }
+    {
+        let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).build().unwrap();
+        assert_eq!(ds.get(1).unwrap(), Some("first run".into()));
+        ds.insert(2, "second run".into()).unwrap();
+    }
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("first run".into()));
+    assert_eq!(ds.get(2).unwrap(), Some("second run".into()));
+    drop(ds);
+    let ds: DingoStore = DingoStore::new(&prefix).unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("first run".into()));
+    assert_eq!(ds.stats().unwrap().sstables, 2);
+}
+
+#[test]
+fn tables_missing_from_the_manifest_are_ignored() {
+    let (dir, prefix) = common::store("manifest_uncommitted");
+    let (other_dir, other) = common::store("manifest_uncommitted_other");
+    for (prefix, value) in [(&prefix, "committed"), (&other, "uncommitted")] {
+        let mut ds: DingoStore = DingoStore::open(prefix).unwrap();
+        ds.insert(1, value.into()).unwrap();
+    }
+    assert_eq!(common::files(&dir, ".manifest").len(), 1);
+    // A table written by a flush that died before the manifest listed it, newer than the rest.
+    let table = &common::files(&other_dir, ".data")[0];
+    std::fs::copy(table, format!("{}_99999999999999.data", prefix)).unwrap();
+
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("committed".into()));
+    assert_eq!(ds.stats().unwrap().sstables, 1);
+}
+
+#[test]
+fn manifests_from_a_newer_version_are_refused() {
+    let (_dir, prefix) = common::store("manifest_version");
+    {
+        let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        ds.insert(1, "one".into()).unwrap();
+        ds.flush().unwrap();
+    }
+    let path = format!("{}.manifest", prefix);
+    let mut bytes = std::fs::read(&path).unwrap();
+    assert_eq!(&bytes[..5], &[0xD1, 0xE6, 0x3A, 0x4F, 1]);
+
+    bytes[4] = 2;
+    std::fs::write(&path, &bytes).unwrap();
+    match DingoStore::<u64, String>::open(&prefix).err() {
+        Some(DingoError::UnsupportedVersion { file, version }) => {
+            assert_eq!(file, path);
+            assert_eq!(version, 2);
+        }
+        other => panic!("expected a version error, got {:?}", other),
+    }
+    // Without the magic it's no manifest at all.
+    bytes[..5].copy_from_slice(&[0, 0, 0, 1, 1]);
+    std::fs::write(&path, &bytes).unwrap();
+    assert!(matches!(DingoStore::<u64, String>::open(&prefix).err(), Some(DingoError::Corruption { offset: 0, .. })));
+}
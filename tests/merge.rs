@@ -0,0 +1,74 @@
+mod common;
+
+use dingodb::dingostore::{add_integers, DingoError, DingoStore, DingoStoreBuilder, Durability};
+
+#[test]
+fn increments_add_up_across_flushes_and_compaction() {
+    let (_dir, prefix) = common::store("merge_counter");
+    let build = || DingoStoreBuilder::new(&prefix).merge_operator(add_integers).compaction_trigger(100);
+    let mut ds: DingoStore = build().build().unwrap();
+    ds.insert(1, "10".into()).unwrap();
+    ds.merge(1, "1".into()).unwrap();
+    ds.flush().unwrap();
+    ds.merge(1, "2".into()).unwrap();
+    ds.merge(2, "5".into()).unwrap();
+    ds.flush().unwrap();
+    ds.merge(1, "3".into()).unwrap();
+    ds.merge(2, "-1".into()).unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("16".into()));
+    assert_eq!(ds.get(2).unwrap(), Some("4".into()));
+    assert_eq!(ds.range(..).unwrap().map(Result::unwrap).collect::<Vec<_>>(), vec![(1, "16".into()), (2, "4".into())]);
+
+    // A value written after the operands replaces them.
+    ds.insert(2, "100".into()).unwrap();
+    ds.merge(2, "1".into()).unwrap();
+    ds.flush().unwrap();
+    ds.compact_now().unwrap();
+    drop(ds);
+    let mut ds: DingoStore = build().open().unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("16".into()));
+    assert_eq!(ds.get(2).unwrap(), Some("101".into()));
+    ds.merge(1, "4".into()).unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("20".into()));
+}
+
+#[test]
+fn merging_without_an_operator_is_an_error() {
+    let (_dir, prefix) = common::store("merge_no_operator");
+    let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert!(matches!(ds.merge(1, "1".into()), Err(DingoError::NoMergeOperator)));
+    assert_eq!(ds.get(1).unwrap(), None);
+}
+
+#[test]
+fn an_operator_takes_the_stores_value_type() {
+    let (_dir, prefix) = common::store("merge_value_type");
+    let sum = |value: Option<&u64>, operand: &u64| value.unwrap_or(&0) + operand;
+    let mut ds: DingoStore<u64, u64> = DingoStoreBuilder::new(&prefix).merge_operator(sum).build().unwrap();
+    ds.merge(1, 5).unwrap();
+    ds.flush().unwrap();
+    ds.merge(1, 7).unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some(12));
+}
+
+#[test]
+fn merges_count_once_while_their_memtable_flushes() {
+    let (_dir, prefix) = common::store("merge_flush_handoff");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix)
+        .memtable_size_bytes(400)
+        .compaction_trigger(100)
+        .durability(Durability::NoSync)
+        .merge_operator(add_integers)
+        .build()
+        .unwrap();
+    // Each lookup races the background flush of the memtable before it.
+    for i in 0..5000u64 {
+        ds.merge(i % 40, "1".into()).unwrap();
+        assert_eq!(ds.get(i % 40).unwrap(), Some((i / 40 + 1).to_string()), "after {} merges", i + 1);
+        if i % 7 == 0 {
+            let all: Vec<String> = ds.range(..).unwrap().map(|item| item.unwrap().1).collect();
+            assert!(all.iter().all(|count| count.parse::<u64>().unwrap() <= i / 40 + 1), "after {} merges: {:?}", i + 1, all);
+        }
+    }
+    assert!(ds.stats().unwrap().flushes > 50);
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use dingodb::dingostore::{DingoStore, DingoStoreBuilder};
+
+#[test]
+fn deleted_range_reads_as_gone() {
+    let (_dir, prefix) = common::store("range_delete");
+    let check = |ds: &DingoStore| {
+        for i in 0..100u64 {
+            let want = if (20..30).contains(&i) { None } else { Some(format!("v{}", i)) };
+            assert_eq!(ds.get(i).unwrap(), want, "key {}", i);
+        }
+        let keys: Vec<u64> = ds.range(..).unwrap().map(|item| item.unwrap().0).collect();
+        assert_eq!(keys, (0..20).chain(30..100).collect::<Vec<u64>>());
+    };
+    {
+        let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        for i in 0..100u64 {
+            ds.insert(i, format!("v{}", i)).unwrap();
+            if i % 25 == 24 {
+                ds.flush().unwrap();
+            }
+        }
+        ds.delete_range(20..30).unwrap();
+        check(&ds);
+        ds.flush().unwrap();
+        check(&ds);
+    }
+    let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    check(&ds);
+    ds.compact_now().unwrap();
+    check(&ds);
+    assert_eq!(ds.stats().unwrap().sstables, 1);
+}
+
+#[test]
+fn unbounded_end_stops_at_the_last_key() {
+    let (_dir, prefix) = common::store("range_delete_unbounded");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).build().unwrap();
+    for i in 0..50u64 {
+        ds.insert(i, format!("v{}", i)).unwrap();
+    }
+    ds.flush().unwrap();
+    ds.delete_range(40..).unwrap();
+    ds.insert(60, "later".into()).unwrap();
+    assert_eq!(ds.get(45).unwrap(), None);
+    assert_eq!(ds.get(60).unwrap(), Some("later".into()));
+    assert_eq!(ds.range(35..).unwrap().count(), 6);
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use dingodb::dingostore::{DingoStore, DingoStoreBuilder};
+
+#[test]
+fn keys_below_every_table_and_empty_stores_read_as_absent() {
+    let (_dir, prefix) = common::store("reads_small_key");
+    let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.get(0).unwrap(), None);
+    assert_eq!(ds.get(3).unwrap(), None);
+    for i in 10..20u64 {
+        ds.insert(i, format!("v{}", i)).unwrap();
+    }
+    ds.flush().unwrap();
+    assert_eq!(ds.get(3).unwrap(), None);
+    assert_eq!(ds.get(0).unwrap(), None);
+    assert_eq!(ds.get(10).unwrap(), Some("v10".into()));
+}
+
+#[test]
+fn newest_table_wins() {
+    let (_dir, prefix) = common::store("reads_newest_table");
+    let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    ds.insert(5, "old".into()).unwrap();
+    ds.insert(9, "nine".into()).unwrap();
+    ds.flush().unwrap();
+    ds.insert(1, "one".into()).unwrap();
+    ds.insert(5, "new".into()).unwrap();
+    ds.flush().unwrap();
+    assert_eq!(ds.stats().unwrap().sstables, 2);
+    assert_eq!(ds.get(5).unwrap(), Some("new".into()));
+    assert_eq!(ds.get(9).unwrap(), Some("nine".into()));
+    assert_eq!(ds.get(1).unwrap(), Some("one".into()));
+    drop(ds);
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.get(5).unwrap(), Some("new".into()));
+}
+
+#[test]
+fn a_missing_table_is_an_error() {
+    let (dir, prefix) = common::store("reads_missing_table");
+    let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    ds.insert(1, "one".into()).unwrap();
+    ds.flush().unwrap();
+    for table in common::files(&dir, ".data") {
+        std::fs::remove_file(table).unwrap();
+    }
+    assert!(ds.get(1).is_err());
+    assert!(ds.range(..).is_err() || ds.range(..).unwrap().any(|item| item.is_err()));
+}
+
+#[test]
+fn get_many_matches_get() {
+    let (_dir, prefix) = common::store("reads_get_many");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(3000).cache_capacity(50).build().unwrap();
+    for i in 0..2000u64 {
+        ds.insert(i * 3, format!("v{}", i)).unwrap();
+    }
+    ds.delete(9).unwrap();
+    for i in 0..50u64 {
+        ds.insert(i * 3, format!("w{}", i)).unwrap();
+    }
+    assert!(ds.stats().unwrap().sstables > 1);
+    // Unsorted, with repeats, deleted and absent keys.
+    let keys: Vec<u64> = (0..7000u64).rev().step_by(7).chain([9, 0, 0, 6000, 3]).collect();
+    let single: Vec<Option<String>> = keys.iter().map(|key| ds.get(*key).unwrap()).collect();
+    assert!(single.iter().any(Option::is_none) && single.iter().any(Option::is_some));
+    assert_eq!(ds.get_many(&keys).unwrap(), single);
+    // Again, now that some are cached.
+    assert_eq!(ds.get_many(&keys).unwrap(), single);
+    assert_eq!(ds.get_many(&[]).unwrap(), Vec::<Option<String>>::new());
+}
+
+#[test]
+fn mapped_tables_read_like_plain_ones() {
+    for block_size in [4096, 0] {
+        let mut results = Vec::new();
+        for mmap in [false, true] {
+            let (_dir, prefix) = common::store(&format!("reads_mmap_{}_{}", block_size, mmap));
+            let mut ds: DingoStore =
+                DingoStoreBuilder::new(&prefix).block_size(block_size).mmap(mmap).compaction_trigger(100).build().unwrap();
+            for i in 0..2000u64 {
+                ds.insert(i * 3 % 2000, format!("v{}", i)).unwrap();
+                if i % 500 == 499 {
+                    ds.flush().unwrap();
+                }
+            }
+            let got: Vec<Option<String>> = (0..4000u64).map(|i| ds.get(i % 2100).unwrap()).collect();
+            let stats = ds.stats().unwrap();
+            assert_eq!(stats.sstables, 4);
+            // However many lookups read a table, it's mapped once.
+            assert_eq!(stats.mmaps, if mmap { 4 } else { 0 }, "block size {}", block_size);
+            results.push(got);
+        }
+        assert_eq!(results[0], results[1], "block size {}", block_size);
+        assert!(results[0][..2000].iter().all(Option::is_some));
+    }
+}
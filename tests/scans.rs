@@ -0,0 +1,139 @@
+mod common;
+
+use dingodb::dingostore::{DingoStore, DingoStoreBuilder};
+
+// Keys 0..300 written three times over, a flush after every 100 writes, so each key's latest
+// value is in a different table than its older ones. Then key 15 is deleted and key 12 rewritten
+// in the memtable.
+fn layered_store(name: &str) -> DingoStore {
+    let (_dir, prefix) = common::store(name);
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).compaction_trigger(100).build().unwrap();
+    for i in 0..900u64 {
+        ds.insert(i % 300, format!("v{}", i)).unwrap();
+        if i % 100 == 99 {
+            ds.flush().unwrap();
+        }
+    }
+    ds.delete(15).unwrap();
+    ds.insert(12, "memtable".into()).unwrap();
+    ds
+}
+
+// What layered_store holds for `key`.
+fn layered_value(key: u64) -> Option<String> {
+    match key {
+        15 => None,
+        12 => Some("memtable".into()),
+        key if key < 300 => Some(format!("v{}", key + 600)),
+        _ => None,
+    }
+}
+
+#[test]
+fn range_yields_the_newest_pairs_in_order() {
+    let ds = layered_store("scans_range");
+    assert!(ds.stats().unwrap().sstables >= 9);
+    let got: Vec<(u64, String)> = ds.range(10..20).unwrap().map(|item| item.unwrap()).collect();
+    let want: Vec<(u64, String)> = (10..20).filter_map(|key| layered_value(key).map(|value| (key, value))).collect();
+    assert_eq!(got, want);
+    assert_eq!(got.len(), 9);
+    assert_eq!(ds.range(..).unwrap().count(), 299);
+    assert_eq!(ds.range(290..=299).unwrap().count(), 10);
+    assert_eq!(ds.range(300..).unwrap().count(), 0);
+}
+
+#[test]
+fn len_counts_each_live_key_once() {
+    let ds = layered_store("scans_len");
+    assert_eq!(ds.len().unwrap(), 299);
+    assert!(!ds.is_empty().unwrap());
+    assert!(ds.contains_key(12).unwrap());
+    assert!(ds.contains_key(299).unwrap());
+    assert!(!ds.contains_key(15).unwrap());
+    assert!(!ds.contains_key(300).unwrap());
+
+    let (_dir, prefix) = common::store("scans_len_memtable");
+    let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert!(ds.is_empty().unwrap());
+    assert_eq!(ds.len().unwrap(), 0);
+    ds.insert(1, "a".into()).unwrap();
+    ds.insert(1, "b".into()).unwrap();
+    ds.delete(2).unwrap();
+    assert_eq!(ds.len().unwrap(), 1);
+}
+
+#[test]
+fn keys_yields_each_live_key_once_in_order() {
+    let ds = layered_store("scans_keys");
+    let keys: Vec<u64> = ds.keys().unwrap().map(|key| key.unwrap()).collect();
+    let want: Vec<u64> = (0..300).filter(|&key| key != 15).collect();
+    assert_eq!(keys, want);
+}
+
+#[test]
+fn range_rev_walks_back_through_the_newest_pairs() {
+    let ds = layered_store("scans_range_rev");
+    let got: Vec<(u64, String)> = ds.range_rev(10..20).unwrap().map(|item| item.unwrap()).collect();
+    let mut want: Vec<(u64, String)> = ds.range(10..20).unwrap().map(|item| item.unwrap()).collect();
+    want.reverse();
+    assert_eq!(got, want);
+    assert_eq!(got.first().map(|pair| pair.0), Some(19));
+    assert!(got.windows(2).all(|pair| pair[0].0 > pair[1].0));
+    assert_eq!(got.iter().find(|pair| pair.0 == 12).unwrap().1, "memtable");
+    assert!(got.iter().all(|pair| pair.0 != 15));
+    assert_eq!(ds.range_rev(..).unwrap().count(), 299);
+    assert_eq!(ds.range_rev(..=5).unwrap().map(|item| item.unwrap().0).collect::<Vec<_>>(), vec![5, 4, 3, 2, 1, 0]);
+}
+
+#[test]
+#[allow(clippy::reversed_empty_ranges)]
+fn a_reversed_range_is_empty() {
+    let ds = layered_store("scans_reversed");
+    assert_eq!(ds.range(20..10).unwrap().count(), 0);
+    assert_eq!(ds.range_rev(20..10).unwrap().count(), 0);
+    assert_eq!(ds.range(20..=19).unwrap().count(), 0);
+    assert_eq!(ds.range_rev(20..=19).unwrap().count(), 0);
+}
+
+#[test]
+fn a_range_around_no_keys_is_empty() {
+    use std::ops::Bound::{Excluded, Included};
+
+    let ds = layered_store("scans_empty");
+    for range in [(Excluded(5), Excluded(5)), (Included(5), Excluded(5)), (Excluded(5), Included(5))] {
+        assert_eq!(ds.range(range).unwrap().count(), 0, "{:?}", range);
+        assert_eq!(ds.range_rev(range).unwrap().count(), 0, "{:?}", range);
+    }
+    assert_eq!(ds.range((Included(5), Included(5))).unwrap().count(), 1);
+    assert_eq!(ds.range_rev((Included(5), Included(5))).unwrap().count(), 1);
+}
+
+#[test]
+fn scan_prefix_keeps_to_one_tenant() {
+    let (_dir, prefix) = common::store("scans_prefix");
+    let key = |tenant: u64, i: u64| tenant << 40 | i;
+    let mask = !((1u64 << 40) - 1);
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).compaction_trigger(100).build().unwrap();
+    for i in 0..50u64 {
+        ds.insert(key(1, i), format!("one{}", i)).unwrap();
+        ds.insert(key(2, i), format!("two{}", i)).unwrap();
+    }
+    ds.flush().unwrap();
+    ds.insert(key(1, 3), "rewritten".into()).unwrap();
+    ds.delete(key(1, 4)).unwrap();
+    // The last key of tenant 0, just below tenant 1's, and the last key tenant 1's prefix covers.
+    ds.insert(key(1, 0) - 1, "tenant 0".into()).unwrap();
+    ds.insert(key(2, 0) - 1, "last of tenant 1".into()).unwrap();
+
+    let one: Vec<(u64, String)> = ds.scan_prefix(1 << 40, mask).unwrap().map(Result::unwrap).collect();
+    assert_eq!(one.len(), 50);
+    assert!(one.iter().all(|(k, _)| k & mask == 1 << 40));
+    assert!(one.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    assert_eq!(one[3], (key(1, 3), "rewritten".into()));
+    assert!(one.iter().all(|(k, _)| *k != key(1, 4)));
+    assert_eq!(one.last().unwrap().1, "last of tenant 1");
+
+    let two: Vec<u64> = ds.scan_prefix(2 << 40, mask).unwrap().map(|item| item.unwrap().0).collect();
+    assert_eq!(two, (0..50).map(|i| key(2, i)).collect::<Vec<_>>());
+    assert_eq!(ds.scan_prefix(3 << 40, mask).unwrap().count(), 0);
+}
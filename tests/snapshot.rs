@@ -0,0 +1,37 @@
+mod common;
+
+use dingodb::dingostore::{DingoStore, DingoStoreBuilder};
+
+#[test]
+fn snapshots_keep_reading_what_was_there() {
+    let (dir, prefix) = common::store("snapshot");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).compaction_trigger(100).build().unwrap();
+    for i in 0..100u64 {
+        ds.insert(i, format!("a{}", i)).unwrap();
+        if i % 25 == 24 {
+            ds.flush().unwrap();
+        }
+    }
+    ds.insert(200, "in memory".into()).unwrap();
+    let snap = ds.snapshot().unwrap();
+    ds.insert(1, "changed".into()).unwrap();
+    ds.delete(99).unwrap();
+    ds.delete(200).unwrap();
+    assert_eq!(snap.get(1).unwrap(), Some("a1".into()));
+    assert_eq!(ds.get(1).unwrap(), Some("changed".into()));
+    assert_eq!(snap.get(99).unwrap(), Some("a99".into()));
+    assert_eq!(snap.get(200).unwrap(), Some("in memory".into()));
+    assert_eq!(ds.get(200).unwrap(), None);
+
+    // The tables the snapshot reads outlive the compaction that replaces them.
+    ds.flush().unwrap();
+    ds.compact_now().unwrap();
+    assert_eq!(ds.stats().unwrap().sstables, 1);
+    assert!(common::files(&dir, ".data").len() > 1);
+    for i in 0..100u64 {
+        assert_eq!(snap.get(i).unwrap(), Some(format!("a{}", i)), "key {}", i);
+    }
+    assert_eq!(ds.get(99).unwrap(), None);
+    drop(snap);
+    assert_eq!(common::files(&dir, ".data").len(), 1);
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use dingodb::dingostore::{add_integers, DingoStats, DingoStore, DingoStoreBuilder};
+
+#[test]
+fn counters_follow_the_operations() {
+    let (_dir, prefix) = common::store("stats_counters");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).merge_operator(add_integers).build().unwrap();
+    assert_eq!(ds.stats().unwrap(), DingoStats::default());
+
+    for i in 0..10u64 {
+        ds.insert(i, "0123456789".into()).unwrap();
+    }
+    ds.delete(3).unwrap();
+    ds.merge(20, "1".into()).unwrap();
+    ds.merge(20, "2".into()).unwrap();
+    let stats = ds.stats().unwrap();
+    assert_eq!((stats.inserts, stats.deletes, stats.merges), (10, 1, 2));
+    assert!(stats.memtable_bytes > 0);
+    assert_eq!((stats.flushes, stats.sstables), (0, 0));
+
+    ds.flush().unwrap();
+    ds.insert_batch(vec![(30, "a".into()), (31, "b".into())]).unwrap();
+    ds.flush().unwrap();
+    let stats = ds.stats().unwrap();
+    assert_eq!(stats.inserts, 12);
+    assert_eq!((stats.flushes, stats.sstables, stats.memtable_bytes), (2, 2, 0));
+    assert!(stats.disk_bytes > 0);
+
+    // Each of these keys is only in the range of the first table, so only its filter is checked.
+    for i in 0..5u64 {
+        ds.get(i).unwrap();
+    }
+    let stats = ds.stats().unwrap();
+    assert_eq!(stats.gets, 5);
+    assert_eq!(stats.bloom_hits + stats.bloom_misses, 5);
+
+    ds.compact_now().unwrap();
+    let stats = ds.stats().unwrap();
+    assert_eq!((stats.compactions, stats.sstables), (1, 1));
+}
+
+#[test]
+fn a_failed_background_compaction_shows_in_stats() {
+    let (dir, prefix) = common::store("stats_background_error");
+    let mut ds: DingoStore =
+        DingoStoreBuilder::new(&prefix).compaction_trigger(100).compaction_interval(Duration::from_millis(20)).build().unwrap();
+    for i in 0..2u64 {
+        ds.insert(i, "v".into()).unwrap();
+        ds.flush().unwrap();
+    }
+    // Whatever the timer has merged so far, the next compaction reads garbage.
+    let started = Instant::now();
+    while ds.stats().unwrap().background_errors == 0 {
+        assert!(started.elapsed() < Duration::from_secs(10), "no compaction failed");
+        for file in common::files(&dir, ".data") {
+            // The timer may have replaced it already.
+            let _ = std::fs::OpenOptions::new().write(true).open(file).and_then(|mut file| file.write_all(&[0xff; 64]));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(ds.stats().unwrap().last_background_error.is_some());
+}
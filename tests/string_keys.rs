@@ -0,0 +1,32 @@
+mod common;
+
+use dingodb::dingostore::{DingoStore, DingoStoreBuilder};
+
+#[test]
+fn string_keys_round_trip_across_flushes() {
+    let (_dir, prefix) = common::store("string_keys");
+    let build = || DingoStoreBuilder::new(&prefix).memtable_size_bytes(4000).compaction_trigger(3);
+    {
+        let mut ds: DingoStore<String, String> = build().build().unwrap();
+        for i in 0..1000u64 {
+            ds.insert(format!("user:{}", i), format!("v{}", i)).unwrap();
+        }
+        ds.delete("user:17".into()).unwrap();
+        ds.insert_batch(vec![("a".into(), "1".into()), ("zz".into(), "2".into())]).unwrap();
+        assert!(ds.stats().unwrap().flushes > 0);
+        assert_eq!(ds.get("user:5".into()).unwrap(), Some("v5".into()));
+        assert_eq!(ds.get("user:".into()).unwrap(), None);
+        assert_eq!(ds.get("user:17".into()).unwrap(), None);
+    }
+    let ds: DingoStore<String, String> = build().open().unwrap();
+    for i in 0..1000u64 {
+        let want = if i == 17 { None } else { Some(format!("v{}", i)) };
+        assert_eq!(ds.get(format!("user:{}", i)).unwrap(), want);
+    }
+    assert_eq!(ds.get("a".into()).unwrap(), Some("1".into()));
+    // Ordered as strings, not numbers: "user:10" is followed by "user:100".
+    let keys: Vec<String> = ds.range("user:10".to_string().."user:11".to_string()).unwrap().map(|item| item.unwrap().0).collect();
+    assert_eq!(keys.len(), 11);
+    assert_eq!(keys[..3], ["user:10", "user:100", "user:101"]);
+    assert_eq!(ds.len().unwrap(), 1001);
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use dingodb::dingostore::{DingoStore, DingoStoreBuilder};
+
+#[test]
+fn delete_in_the_memtable() {
+    let (_dir, prefix) = common::store("tombstones_memtable");
+    let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    ds.insert(1, "a".into()).unwrap();
+    ds.delete(1).unwrap();
+    assert_eq!(ds.get(1).unwrap(), None);
+    ds.delete(2).unwrap();
+    assert_eq!(ds.get(2).unwrap(), None);
+    ds.insert(1, "back".into()).unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("back".into()));
+}
+
+#[test]
+fn delete_shadows_a_flushed_value() {
+    let (_dir, prefix) = common::store("tombstones_shadow");
+    let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    ds.insert(1, "a".into()).unwrap();
+    ds.insert(2, "b".into()).unwrap();
+    ds.flush().unwrap();
+    ds.delete(1).unwrap();
+    assert_eq!(ds.get(1).unwrap(), None);
+    assert_eq!(ds.get(2).unwrap(), Some("b".into()));
+}
+
+#[test]
+fn tombstones_survive_flushes_and_compaction() {
+    let (_dir, prefix) = common::store("tombstones_flush");
+    let build = || DingoStoreBuilder::new(&prefix).compaction_trigger(100);
+    {
+        let mut ds: DingoStore = build().build().unwrap();
+        for i in 0..10u64 {
+            ds.insert(i, format!("v{}", i)).unwrap();
+        }
+        ds.flush().unwrap();
+        ds.delete(3).unwrap();
+        ds.flush().unwrap();
+        ds.insert(20, "newer".into()).unwrap();
+        ds.flush().unwrap();
+        assert_eq!(ds.get(3).unwrap(), None);
+    }
+    let mut ds: DingoStore = build().open().unwrap();
+    assert_eq!(ds.get(3).unwrap(), None);
+    assert_eq!(ds.get(4).unwrap(), Some("v4".into()));
+    ds.compact_now().unwrap();
+    assert_eq!(ds.get(3).unwrap(), None);
+    assert_eq!(ds.range(..).unwrap().count(), 10);
+}
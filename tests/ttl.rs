@@ -0,0 +1,52 @@
+mod common;
+
+use std::time::Duration;
+
+use dingodb::dingostore::DingoStore;
+
+#[test]
+fn expired_keys_read_as_absent() {
+    let (_dir, prefix) = common::store("ttl_expiry");
+    let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    ds.insert(1, "old".into()).unwrap();
+    ds.insert_with_ttl(3, "flushed".into(), Duration::from_millis(200)).unwrap();
+    ds.flush().unwrap();
+    ds.insert_with_ttl(1, "short".into(), Duration::from_millis(200)).unwrap();
+    ds.insert_with_ttl(2, "long".into(), Duration::from_secs(3600)).unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("short".into()));
+    assert_eq!(ds.get(3).unwrap(), Some("flushed".into()));
+    assert!(ds.contains_key(3).unwrap());
+
+    std::thread::sleep(Duration::from_millis(300));
+    // The expired value hides the older one rather than bringing it back.
+    assert_eq!(ds.get(1).unwrap(), None);
+    assert_eq!(ds.get(3).unwrap(), None);
+    assert!(!ds.contains_key(3).unwrap());
+    assert_eq!(ds.get(2).unwrap(), Some("long".into()));
+    assert_eq!(ds.get_many(&[1, 2, 3]).unwrap(), vec![None, Some("long".into()), None]);
+    assert_eq!(ds.range(..).unwrap().map(|item| item.unwrap().0).collect::<Vec<_>>(), vec![2]);
+    assert_eq!(ds.len().unwrap(), 1);
+
+    ds.flush().unwrap();
+    ds.compact_now().unwrap();
+    drop(ds);
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.get(1).unwrap(), None);
+    assert_eq!(ds.get(2).unwrap(), Some("long".into()));
+}
+
+#[test]
+fn expiry_survives_wal_replay() {
+    let (_dir, prefix) = common::store("ttl_wal");
+    {
+        let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        ds.insert_with_ttl(5, "short".into(), Duration::from_millis(200)).unwrap();
+        ds.insert_with_ttl(6, "long".into(), Duration::from_secs(3600)).unwrap();
+        std::mem::forget(ds);
+    }
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.get(5).unwrap(), Some("short".into()));
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(ds.get(5).unwrap(), None);
+    assert_eq!(ds.get(6).unwrap(), Some("long".into()));
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use serde::{Deserialize, Serialize};
+
+use dingodb::dingostore::{DingoError, DingoStore, DingoStoreBuilder};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Point {
+    x: i32,
+    name: String,
+    tags: Vec<u8>,
+}
+
+#[test]
+fn custom_values_round_trip() {
+    let (_dir, prefix) = common::store("values_struct");
+    let point = |i: u64| Point { x: -(i as i32), name: format!("p{}", i), tags: vec![i as u8; 3] };
+    {
+        let mut ds: DingoStore<u64, Point> = DingoStore::open(&prefix).unwrap();
+        for i in 0..100u64 {
+            ds.insert(i, point(i)).unwrap();
+        }
+        ds.flush().unwrap();
+        ds.insert(100, point(100)).unwrap();
+        assert_eq!(ds.get(100).unwrap(), Some(point(100)));
+        assert_eq!(ds.get(4).unwrap(), Some(point(4)));
+    }
+    let ds: DingoStore<u64, Point> = DingoStore::open(&prefix).unwrap();
+    for i in 0..=100u64 {
+        assert_eq!(ds.get(i).unwrap(), Some(point(i)));
+    }
+    assert_eq!(ds.range(10..13).unwrap().map(|item| item.unwrap().1).collect::<Vec<_>>(), vec![point(10), point(11), point(12)]);
+}
+
+#[test]
+fn oversized_values_are_refused_without_side_effects() {
+    let (dir, prefix) = common::store("values_too_large");
+    let wal = dir.join("db.wal");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(1000).build().unwrap();
+    ds.insert(1, "small".into()).unwrap();
+    let wal_len = std::fs::metadata(&wal).unwrap().len();
+    let memtable_bytes = ds.stats().unwrap().memtable_bytes;
+
+    match ds.insert(5, "x".repeat(2000)) {
+        Err(DingoError::ValueTooLarge { key, len }) => assert_eq!((key, len), (5u64.to_be_bytes().to_vec(), 2008)),
+        other => panic!("expected ValueTooLarge, got {:?}", other.map(|_| ())),
+    }
+    assert_eq!(ds.get(5).unwrap(), None);
+    assert_eq!(std::fs::metadata(&wal).unwrap().len(), wal_len);
+    let stats = ds.stats().unwrap();
+    assert_eq!((stats.inserts, stats.memtable_bytes), (1, memtable_bytes));
+    drop(ds);
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.get(5).unwrap(), None);
+    assert_eq!(ds.get(1).unwrap(), Some("small".into()));
+    drop(ds);
+
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(1000).max_value_bytes(5000).build().unwrap();
+    ds.insert(5, "x".repeat(2000)).unwrap();
+    assert_eq!(ds.get(5).unwrap(), Some("x".repeat(2000)));
+}
+
+#[test]
+fn invalid_utf8_is_an_error_not_a_lossy_string() {
+    let (_dir, prefix) = common::store("values_invalid_utf8");
+    let table = {
+        let mut ds: DingoStore<u64, Vec<u8>> = DingoStore::open(&prefix).unwrap();
+        ds.insert(1, b"fine".to_vec()).unwrap();
+        ds.insert(2, vec![b'a', 0xff, 0xfe]).unwrap();
+        ds.flush().unwrap().unwrap()
+    };
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("fine".into()));
+    let err = ds.get(2).unwrap_err();
+    assert!(err.to_string().contains("not valid UTF-8"), "{}", err);
+    match err {
+        DingoError::InvalidUtf8 { key, file } => assert_eq!((key, file), (2u64.to_be_bytes().to_vec(), table)),
+        other => panic!("expected InvalidUtf8, got {:?}", other),
+    }
+    assert!(ds.range(..).unwrap().any(|item| matches!(item, Err(DingoError::InvalidUtf8 { .. }))));
+}
+
+#[test]
+fn insert_returning_old_hands_back_the_previous_value() {
+    let (_dir, prefix) = common::store("values_returning_old");
+    let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.insert_returning_old(1, "first".into()).unwrap(), None);
+    assert_eq!(ds.insert_returning_old(1, "second".into()).unwrap(), Some("first".into()));
+    assert_eq!(ds.get(1).unwrap(), Some("second".into()));
+
+    // The previous value is found in an SSTable too, and a deleted one doesn't come back.
+    ds.insert(2, "flushed".into()).unwrap();
+    ds.flush().unwrap();
+    assert_eq!(ds.insert_returning_old(2, "new".into()).unwrap(), Some("flushed".into()));
+    ds.delete(1).unwrap();
+    assert_eq!(ds.insert_returning_old(1, "again".into()).unwrap(), None);
+    assert_eq!(ds.stats().unwrap().inserts, 5);
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use dingodb::dingostore::{DingoStore, DingoStoreBuilder, Durability};
+
+#[test]
+fn a_batch_spanning_the_flush_threshold_reads_back() {
+    let (_dir, prefix) = common::store("writes_batch");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(2000).build().unwrap();
+    for i in 0..50u64 {
+        ds.insert(i, "before".into()).unwrap();
+    }
+    let flushes = ds.stats().unwrap().flushes;
+    // Far bigger than the memtable, and still applied whole.
+    ds.insert_batch((0..500u64).map(|k| (k, format!("b{}", k))).collect()).unwrap();
+    ds.insert(1000, "after".into()).unwrap();
+    assert!(ds.stats().unwrap().flushes > flushes);
+    for k in 0..500u64 {
+        assert_eq!(ds.get(k).unwrap(), Some(format!("b{}", k)));
+    }
+    drop(ds);
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    for k in 0..500u64 {
+        assert_eq!(ds.get(k).unwrap(), Some(format!("b{}", k)));
+    }
+    assert_eq!(ds.get(1000).unwrap(), Some("after".into()));
+}
+
+#[test]
+fn a_torn_batch_is_not_replayed() {
+    let (_dir, prefix) = common::store("writes_torn_batch");
+    {
+        let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        ds.insert(1, "one".into()).unwrap();
+        ds.insert_batch((100..200u64).map(|k| (k, format!("b{}", k))).collect()).unwrap();
+        std::mem::forget(ds);
+    }
+    // The process died while the batch was being logged.
+    let wal = format!("{}.wal", prefix);
+    let len = std::fs::metadata(&wal).unwrap().len();
+    std::fs::OpenOptions::new().write(true).open(&wal).unwrap().set_len(len - 3).unwrap();
+
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.get(1).unwrap(), Some("one".into()));
+    assert_eq!(ds.get(100).unwrap(), None);
+    assert_eq!(ds.range(..).unwrap().count(), 1);
+}
+
+#[test]
+fn memtable_size_is_the_serialized_size() {
+    let (_dir, prefix) = common::store("writes_memtable_size");
+    let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    for len in 0..50u64 {
+        ds.insert(len, "x".repeat(len as usize)).unwrap();
+    }
+    // Each record is the key, the value's length and the value, which bincode writes as its own
+    // u64 length and the bytes of the string.
+    let want: u32 = (0..50).map(|len| 8 + 4 + 8 + len).sum();
+    assert_eq!(ds.stats().unwrap().memtable_bytes, want);
+    assert_eq!(std::fs::metadata(format!("{}.wal", prefix)).unwrap().len(), want as u64);
+    // An overwrite only counts the size difference.
+    ds.insert(49, String::new()).unwrap();
+    assert_eq!(ds.stats().unwrap().memtable_bytes, want - 49);
+}
+
+#[test]
+fn rewriting_a_key_doesnt_fill_the_memtable() {
+    let (_dir, prefix) = common::store("writes_rewrite");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(2000).build().unwrap();
+    // Two of these don't fit in the memtable together, but each replaces the one before.
+    for i in 0..100u64 {
+        ds.insert(1, format!("{:1000}", i)).unwrap();
+    }
+    let stats = ds.stats().unwrap();
+    assert_eq!((stats.flushes, stats.memtable_bytes), (0, 8 + 4 + 8 + 1000));
+    assert_eq!(ds.get(1).unwrap(), Some(format!("{:1000}", 99)));
+}
+
+#[test]
+fn keys_stay_readable_while_flushes_run_in_the_background() {
+    let (_dir, prefix) = common::store("writes_background_flush");
+    let mut ds: DingoStore = DingoStoreBuilder::new(&prefix).memtable_size_bytes(2000).compaction_trigger(4).durability(Durability::NoSync).build().unwrap();
+    for i in 0..3000u64 {
+        ds.insert(i, format!("v{}", i)).unwrap();
+        // The memtable being flushed is still read until its table is in place.
+        for j in i.saturating_sub(30)..=i {
+            assert_eq!(ds.get(j).unwrap(), Some(format!("v{}", j)), "key {} after inserting {}", j, i);
+        }
+        if i % 251 == 0 {
+            assert_eq!(ds.range(..=i).unwrap().count() as u64, i + 1);
+        }
+    }
+    assert!(ds.stats().unwrap().flushes > 20);
+    drop(ds);
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert_eq!(ds.range(..).unwrap().count(), 3000);
+}
+
+#[test]
+fn a_flush_cut_short_is_replayed() {
+    let (_dir, prefix) = common::store("writes_interrupted_flush");
+    {
+        let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        for i in 0..10u64 {
+            ds.insert(i, format!("a{}", i)).unwrap();
+        }
+        std::mem::forget(ds);
+    }
+    // As a flush leaves the log it rotated out until its table is in the manifest.
+    std::fs::rename(format!("{}.wal", prefix), format!("{}.wal.flushing", prefix)).unwrap();
+    {
+        let mut ds: DingoStore = DingoStore::open(&prefix).unwrap();
+        for i in 5..15u64 {
+            ds.insert(i, format!("b{}", i)).unwrap();
+        }
+        assert_eq!(ds.get(2).unwrap(), Some("a2".into()));
+        std::mem::forget(ds);
+    }
+    let ds: DingoStore = DingoStore::open(&prefix).unwrap();
+    assert!(!std::path::Path::new(&format!("{}.wal.flushing", prefix)).exists());
+    for i in 0..15u64 {
+        let want = if i < 5 { format!("a{}", i) } else { format!("b{}", i) };
+        assert_eq!(ds.get(i).unwrap(), Some(want));
+    }
+}